@@ -0,0 +1,411 @@
+// Arithmetic evaluation for is/2 and the arithmetic comparison predicates
+use crate::error::PrologError;
+use crate::tokenizer::{Number, Term};
+use crate::unificator::{format_term, Substitution};
+use num_bigint::BigInt;
+use num_integer::Integer as _;
+use num_rational::BigRational;
+use num_traits::{FromPrimitive, One, Signed, ToPrimitive, Zero};
+use std::cmp::Ordering;
+
+// Evaluates an arithmetic expression under the given bindings
+pub fn evaluate(term: &Term, subs: &Substitution) -> Result<Number, PrologError> {
+    match term {
+        Term::Number(n) => Ok(n.clone()),
+        Term::Variable(v) => match subs.get(v) {
+            Some(bound) => evaluate(bound, subs),
+            None => Err(PrologError::Instantiation),
+        },
+        Term::Constant(name) => evaluate_constant(name),
+        Term::String(_) => Err(PrologError::type_error("evaluable", format_term(term))),
+        Term::Compound { name, args } => match args.as_slice() {
+            [x] => evaluate_unary(name, evaluate(x, subs)?),
+            [x, y] => evaluate_binary(name, evaluate(x, subs)?, evaluate(y, subs)?),
+            _ => Err(PrologError::type_error("evaluable", format!("{}/{}", name, args.len()))),
+        },
+    }
+}
+
+// Compares two expressions numerically, so that 1 =:= 1.0 holds
+pub fn compare(left: &Term, right: &Term, subs: &Substitution) -> Result<Option<Ordering>, PrologError> {
+    Ok(compare_numbers(&evaluate(left, subs)?, &evaluate(right, subs)?))
+}
+
+fn as_float(n: &Number) -> f64 {
+    match n {
+        Number::Integer(i) => *i as f64,
+        Number::BigInt(i) => i.to_f64().unwrap_or(f64::NAN),
+        Number::Rational(r) => r.to_f64().unwrap_or(f64::NAN),
+        Number::Float(f) => *f,
+    }
+}
+
+fn as_bigint(n: &Number) -> Option<BigInt> {
+    match n {
+        Number::Integer(i) => Some(BigInt::from(*i)),
+        Number::BigInt(i) => Some(i.clone()),
+        _ => None,
+    }
+}
+
+fn as_rational(n: &Number) -> Option<BigRational> {
+    match n {
+        Number::Rational(r) => Some(r.clone()),
+        Number::Float(_) => None,
+        _ => as_bigint(n).map(BigRational::from_integer),
+    }
+}
+
+fn require_integer(n: &Number) -> Result<BigInt, PrologError> {
+    as_bigint(n).ok_or_else(|| PrologError::type_error("integer", n))
+}
+
+// Rejects infinite and NaN results, which are not valid Prolog floats
+fn check_float(f: f64) -> Result<Number, PrologError> {
+    if f.is_nan() {
+        Err(PrologError::evaluation_error("undefined"))
+    } else if f.is_infinite() {
+        Err(PrologError::evaluation_error("float_overflow"))
+    } else {
+        Ok(Number::Float(f))
+    }
+}
+
+fn float_to_integer(f: f64) -> Result<Number, PrologError> {
+    BigInt::from_f64(f).map(Number::from_bigint).ok_or_else(|| PrologError::evaluation_error("undefined"))
+}
+
+// Converts a shift amount or exponent to u32, refusing values too large to represent the result
+fn small_exponent(n: &BigInt) -> Result<u32, PrologError> {
+    n.to_u32().ok_or_else(|| PrologError::resource_error("memory"))
+}
+
+// Applies an operation in the narrowest representation that holds both operands: i64 first
+// (falling back to BigInt when `int` overflows), then BigInt, Rational and finally Float.
+fn promote(
+    x: &Number,
+    y: &Number,
+    int: fn(i64, i64) -> Option<i64>,
+    big: impl Fn(BigInt, BigInt) -> Result<Number, PrologError>,
+    rational: impl Fn(BigRational, BigRational) -> Result<Number, PrologError>,
+    float: fn(f64, f64) -> f64,
+) -> Result<Number, PrologError> {
+    if let (Number::Integer(a), Number::Integer(b)) = (x, y) {
+        if let Some(result) = int(*a, *b) {
+            return Ok(Number::Integer(result));
+        }
+    }
+    if let (Some(a), Some(b)) = (as_bigint(x), as_bigint(y)) {
+        return big(a, b);
+    }
+    match (as_rational(x), as_rational(y)) {
+        (Some(a), Some(b)) => rational(a, b),
+        _ => check_float(float(as_float(x), as_float(y))),
+    }
+}
+
+fn evaluate_constant(name: &str) -> Result<Number, PrologError> {
+    match name {
+        "pi" => Ok(Number::Float(std::f64::consts::PI)),
+        "e" => Ok(Number::Float(std::f64::consts::E)),
+        // like any other float result, they are not valid Prolog floats and raise the matching evaluation error
+        "inf" | "infinite" => check_float(f64::INFINITY),
+        "nan" => check_float(f64::NAN),
+        "epsilon" => Ok(Number::Float(f64::EPSILON)),
+        "max_integer" => Ok(Number::Integer(i64::MAX)),
+        "min_integer" => Ok(Number::Integer(i64::MIN)),
+        _ => Err(PrologError::type_error("evaluable", format!("{}/0", name))),
+    }
+}
+
+fn evaluate_unary(name: &str, x: Number) -> Result<Number, PrologError> {
+    use Number::{BigInt as Big, Float, Integer, Rational};
+    match (name, &x) {
+        ("-", _) => Ok(-x),
+        ("+", _) => Ok(x),
+        ("abs", Float(f)) => Ok(Float(f.abs())),
+        ("abs", _) if compare_numbers(&x, &Integer(0)) == Some(Ordering::Less) => Ok(-x),
+        ("abs", _) => Ok(x),
+        ("sign", Integer(i)) => Ok(Integer(i.signum())),
+        ("sign", Big(i)) => Ok(Integer(if i.is_negative() { -1 } else { 1 })),
+        ("sign", Rational(r)) => Ok(Integer(if r.is_negative() { -1 } else { 1 })),
+        ("sign", Float(f)) => Ok(Float(if *f == 0.0 { 0.0 } else { f.signum() })),
+        ("sqrt", _) if as_float(&x) < 0.0 => Err(PrologError::evaluation_error("undefined")),
+        ("sqrt", _) => check_float(as_float(&x).sqrt()),
+        ("sin", _) => check_float(as_float(&x).sin()),
+        ("cos", _) => check_float(as_float(&x).cos()),
+        ("tan", _) => check_float(as_float(&x).tan()),
+        ("asin", _) => check_float(as_float(&x).asin()),
+        ("acos", _) => check_float(as_float(&x).acos()),
+        ("atan", _) => check_float(as_float(&x).atan()),
+        ("exp", _) => check_float(as_float(&x).exp()),
+        ("log", _) | ("log2", _) if as_float(&x) <= 0.0 => Err(PrologError::evaluation_error("undefined")),
+        ("log", _) => check_float(as_float(&x).ln()),
+        ("log2", _) => check_float(as_float(&x).log2()),
+        ("float", _) => check_float(as_float(&x)),
+        ("float_integer_part", _) => Ok(Float(as_float(&x).trunc())),
+        ("float_fractional_part", _) => Ok(Float(as_float(&x).fract())),
+        ("integer" | "truncate" | "round" | "ceiling" | "floor", Integer(_) | Big(_)) => Ok(x),
+        ("integer" | "round", Rational(r)) => Ok(Number::from_bigint(r.round().to_integer())),
+        ("truncate", Rational(r)) => Ok(Number::from_bigint(r.trunc().to_integer())),
+        ("ceiling", Rational(r)) => Ok(Number::from_bigint(r.ceil().to_integer())),
+        ("floor", Rational(r)) => Ok(Number::from_bigint(r.floor().to_integer())),
+        ("integer" | "round", Float(f)) => float_to_integer(f.round()),
+        ("truncate", Float(f)) => float_to_integer(f.trunc()),
+        ("ceiling", Float(f)) => float_to_integer(f.ceil()),
+        ("floor", Float(f)) => float_to_integer(f.floor()),
+        // Floats are converted exactly, so rational(0.1) is not 1r10
+        ("rational" | "rationalize", Float(f)) => BigRational::from_float(*f)
+            .map(Number::from_rational)
+            .ok_or_else(|| PrologError::evaluation_error("undefined")),
+        ("rational" | "rationalize", _) => Ok(x),
+        ("numerator", Rational(r)) => Ok(Number::from_bigint(r.numer().clone())),
+        ("denominator", Rational(r)) => Ok(Number::from_bigint(r.denom().clone())),
+        ("numerator", Integer(_) | Big(_)) => Ok(x),
+        ("denominator", Integer(_) | Big(_)) => Ok(Integer(1)),
+        ("numerator" | "denominator", _) => Err(PrologError::type_error("rational", x)),
+        ("\\", _) => Ok(Number::from_bigint(!require_integer(&x)?)),
+        ("msb", _) => match require_integer(&x)? {
+            i if i.is_positive() => Ok(Integer(i.bits() as i64 - 1)),
+            _ => Err(PrologError::domain_error("not_less_than_one", x)),
+        },
+        _ => Err(PrologError::type_error("evaluable", format!("{}/1", name))),
+    }
+}
+
+fn evaluate_binary(name: &str, x: Number, y: Number) -> Result<Number, PrologError> {
+    use Number::Float;
+    let big = |n: BigInt| -> Result<Number, PrologError> { Ok(Number::from_bigint(n)) };
+    let rational = |r: BigRational| -> Result<Number, PrologError> { Ok(Number::from_rational(r)) };
+    match name {
+        "+" => promote(&x, &y, i64::checked_add, |a, b| big(a + b), |a, b| rational(a + b), |a, b| a + b),
+        "-" => promote(&x, &y, i64::checked_sub, |a, b| big(a - b), |a, b| rational(a - b), |a, b| a - b),
+        "*" => promote(&x, &y, i64::checked_mul, |a, b| big(a * b), |a, b| rational(a * b), |a, b| a * b),
+
+        // Division of integers stays an integer when it is exact, otherwise the result is a float;
+        // as soon as a rational is involved the result is an exact rational
+        "/" if as_float(&y) == 0.0 => Err(PrologError::evaluation_error("zero_divisor")),
+        "/" => promote(
+            &x,
+            &y,
+            |a, b| a.checked_rem(b).filter(|&r| r == 0).and_then(|_| a.checked_div(b)),
+            |a, b| if (&a % &b).is_zero() { big(a / b) } else { check_float(as_float(&x) / as_float(&y)) },
+            |a, b| rational(a / b),
+            |a, b| a / b,
+        ),
+        "rdiv" => match (as_rational(&x), as_rational(&y)) {
+            (_, Some(b)) if b.is_zero() => Err(PrologError::evaluation_error("zero_divisor")),
+            (Some(a), Some(b)) => rational(a / b),
+            (None, _) => Err(PrologError::type_error("rational", x)),
+            (_, None) => Err(PrologError::type_error("rational", y)),
+        },
+
+        "//" | "mod" | "rem" | "div" => {
+            let (a, b) = (require_integer(&x)?, require_integer(&y)?);
+            if b.is_zero() {
+                return Err(PrologError::evaluation_error("zero_divisor"));
+            }
+            // mod and div round towards negative infinity, so mod takes the sign of the divisor
+            big(match name {
+                "//" => a / b,
+                "rem" => a % b,
+                "mod" => a.mod_floor(&b),
+                _ => a.div_floor(&b),
+            })
+        }
+
+        "min" => Ok(if compare_numbers(&x, &y) == Some(Ordering::Greater) { y } else { x }),
+        "max" => Ok(if compare_numbers(&x, &y) == Some(Ordering::Less) { y } else { x }),
+
+        // Integer and rational powers stay exact; a negative integer exponent is only defined
+        // for the bases 1 and -1, except for ** which falls back to a float
+        "**" | "^" if matches!(x, Float(_)) || matches!(y, Float(_) | Number::Rational(_)) => {
+            check_float(as_float(&x).powf(as_float(&y)))
+        }
+        "**" | "^" => {
+            let exponent = require_integer(&y)?;
+            let base = as_rational(&x).unwrap_or_default();
+            if base.is_zero() && exponent.is_negative() {
+                return Err(PrologError::evaluation_error("zero_divisor"));
+            }
+            if exponent.is_negative() && base.is_integer() && !base.abs().is_one() {
+                return match name {
+                    "**" => check_float(as_float(&x).powf(as_float(&y))),
+                    _ => Err(PrologError::type_error("float", x)),
+                };
+            }
+            let power = base.pow(exponent.magnitude().to_i32().ok_or_else(|| PrologError::resource_error("memory"))?);
+            rational(if exponent.is_negative() { power.recip() } else { power })
+        }
+
+        "atan2" | "atan" => check_float(as_float(&x).atan2(as_float(&y))),
+        "copysign" => check_float(as_float(&x).copysign(as_float(&y))),
+        "log" if as_float(&x) <= 0.0 || as_float(&y) <= 0.0 => Err(PrologError::evaluation_error("undefined")),
+        "log" => check_float(as_float(&y).ln() / as_float(&x).ln()),
+
+        ">>" | "<<" | "/\\" | "\\/" | "xor" | "gcd" => {
+            let (a, b) = (require_integer(&x)?, require_integer(&y)?);
+            big(match name {
+                // Shifting right rounds towards negative infinity, like an arithmetic shift
+                ">>" if b.is_negative() => a << small_exponent(&-b)?,
+                ">>" => match b.to_u32() {
+                    Some(shift) => a >> shift,
+                    None => BigInt::from(if a.is_negative() { -1 } else { 0 }),
+                },
+                "<<" if b.is_negative() => a >> small_exponent(&-b)?,
+                "<<" => a << small_exponent(&b)?,
+                "/\\" => a & b,
+                "\\/" => a | b,
+                "xor" => a ^ b,
+                _ => a.gcd(&b),
+            })
+        }
+
+        _ => Err(PrologError::type_error("evaluable", format!("{}/2", name))),
+    }
+}
+
+// Compares numbers by exact value; only NaN is unordered
+pub(crate) fn compare_numbers(x: &Number, y: &Number) -> Option<Ordering> {
+    match (x, y) {
+        (Number::Integer(a), Number::Integer(b)) => Some(a.cmp(b)),
+        (Number::Float(a), Number::Float(b)) => a.partial_cmp(b),
+        (Number::Float(f), n) => compare_float(*f, n),
+        (n, Number::Float(f)) => compare_float(*f, n).map(Ordering::reverse),
+        _ => Some(as_rational(x)?.cmp(&as_rational(y)?)),
+    }
+}
+
+// Compares a float with an exact number without losing precision on either side
+fn compare_float(f: f64, n: &Number) -> Option<Ordering> {
+    if f.is_nan() {
+        None
+    } else if f.is_infinite() {
+        Some(if f > 0.0 { Ordering::Greater } else { Ordering::Less })
+    } else {
+        Some(BigRational::from_float(f)?.cmp(&as_rational(n)?))
+    }
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_query;
+    use crate::tokenizer::tokenize;
+
+    fn eval(expr: &str) -> Result<Number, String> { // helper
+        let query = parse_query(tokenize(&format!("?- X is {}.", expr)).unwrap()).unwrap();
+        evaluate(&query[0].args[1], &Substitution::new()).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn test_integer_arithmetic() {
+        assert_eq!(eval("1 + 2 * 3"), Ok(Number::Integer(7)));
+        assert_eq!(eval("(1 + 2) * 3"), Ok(Number::Integer(9)));
+        assert_eq!(eval("10 - 4 - 3"), Ok(Number::Integer(3)));
+        assert_eq!(eval("-7 // 2"), Ok(Number::Integer(-3)));
+        assert_eq!(eval("-7 mod 2"), Ok(Number::Integer(1)));
+        assert_eq!(eval("7 mod -2"), Ok(Number::Integer(-1)));
+        assert_eq!(eval("-7 rem 2"), Ok(Number::Integer(-1)));
+        assert_eq!(eval("-7 div 2"), Ok(Number::Integer(-4)));
+        assert_eq!(eval("2 ^ 10"), Ok(Number::Integer(1024)));
+        assert_eq!(eval("2 ** 3"), Ok(Number::Integer(8)));
+        assert_eq!(eval("max(3, 5) - min(3, 5)"), Ok(Number::Integer(2)));
+        assert_eq!(eval("abs(-4) + sign(-4)"), Ok(Number::Integer(3)));
+        assert_eq!(eval("5 /\\ 3 + (1 << 4) + (32 >> 1)"), Ok(Number::Integer(33)));
+        assert_eq!(eval("- (3)"), Ok(Number::Integer(-3)));
+    }
+
+    #[test]
+    fn test_float_arithmetic() {
+        assert_eq!(eval("7 / 2"), Ok(Number::Float(3.5)));
+        assert_eq!(eval("8 / 2"), Ok(Number::Integer(4)));
+        assert_eq!(eval("sqrt(16)"), Ok(Number::Float(4.0)));
+        assert_eq!(eval("2 ** -1"), Ok(Number::Float(0.5)));
+        assert_eq!(eval("1.5 + 1"), Ok(Number::Float(2.5)));
+        assert_eq!(eval("truncate(3.7) + round(3.5) + floor(-0.5) + ceiling(0.2)"), Ok(Number::Integer(7)));
+        assert_eq!(eval("float(1)"), Ok(Number::Float(1.0)));
+        assert_eq!(eval("integer(2.5)"), Ok(Number::Integer(3)));
+    }
+
+    #[test]
+    fn test_arithmetic_errors() {
+        assert_eq!(eval("Y + 1"), Err("Instantiation error".to_string()));
+        assert_eq!(eval("foo + 1"), Err("Type error: evaluable expected, found foo/0".to_string()));
+        assert_eq!(eval("bar(1, 2, 3)"), Err("Type error: evaluable expected, found bar/3".to_string()));
+        assert_eq!(eval("1 / 0"), Err("Evaluation error: zero_divisor".to_string()));
+        assert_eq!(eval("1 mod 0"), Err("Evaluation error: zero_divisor".to_string()));
+        assert_eq!(eval("2.0 // 1"), Err("Type error: integer expected, found 2.0".to_string()));
+        assert_eq!(eval("sqrt(-1)"), Err("Evaluation error: undefined".to_string()));
+        assert_eq!(eval("2 ^ -1"), Err("Type error: float expected, found 2".to_string()));
+        assert_eq!(eval("1 rdiv 0"), Err("Evaluation error: zero_divisor".to_string()));
+        assert_eq!(eval("1.5 rdiv 2"), Err("Type error: rational expected, found 1.5".to_string()));
+        assert_eq!(eval("numerator(0.5)"), Err("Type error: rational expected, found 0.5".to_string()));
+        assert_eq!(eval("inf"), Err("Evaluation error: float_overflow".to_string()));
+        assert_eq!(eval("nan + 1"), Err("Evaluation error: undefined".to_string()));
+    }
+
+    fn big(digits: &str) -> Result<Number, String> {
+        Ok(Number::BigInt(digits.parse().unwrap()))
+    }
+
+    fn rational(numerator: i64, denominator: i64) -> Result<Number, String> {
+        Ok(Number::Rational(BigRational::new(numerator.into(), denominator.into())))
+    }
+
+    #[test]
+    fn test_bignum_arithmetic() {
+        assert_eq!(eval("9223372036854775807 + 1"), big("9223372036854775808"));
+        assert_eq!(eval("-9223372036854775808 - 1"), big("-9223372036854775809"));
+        assert_eq!(eval("2 ** 100"), big("1267650600228229401496703205376"));
+        assert_eq!(eval("2 ^ 100 - 2 ^ 100 + 1"), Ok(Number::Integer(1)));
+        assert_eq!(eval("(2 ** 100) // (2 ** 98)"), Ok(Number::Integer(4)));
+        assert_eq!(eval("(2 ** 100 + 4) mod 7"), Ok(Number::Integer(6)));
+        assert_eq!(eval("-(2 ** 64) div 3"), Ok(Number::Integer(-6148914691236517206)));
+        assert_eq!(eval("1 << 70"), big("1180591620717411303424"));
+        assert_eq!(eval("(1 << 70) >> 69"), Ok(Number::Integer(2)));
+        assert_eq!(eval("msb(1 << 70)"), Ok(Number::Integer(70)));
+        assert_eq!(eval("msb(0)"), Err("Domain error: not_less_than_one expected, found 0".to_string()));
+        assert_eq!(eval("msb(1.5)"), Err("Type error: integer expected, found 1.5".to_string()));
+        assert_eq!(eval("gcd(2 ** 80, 6 ** 70)"), big("1180591620717411303424"));
+        assert_eq!(eval("abs(-9223372036854775808)"), big("9223372036854775808"));
+        assert_eq!(eval("2 ** 64 / 2 ** 62"), Ok(Number::Integer(4)));
+        assert_eq!(eval("float(2 ** 64)"), Ok(Number::Float(18446744073709551616.0)));
+        assert_eq!(eval("truncate(1.0e20)"), big("100000000000000000000"));
+    }
+
+    #[test]
+    fn test_rational_arithmetic() {
+        assert_eq!(eval("1r3 + 1r6"), rational(1, 2));
+        assert_eq!(eval("1r3 * 3"), Ok(Number::Integer(1)));
+        assert_eq!(eval("1 rdiv 3 - 1r3"), Ok(Number::Integer(0)));
+        assert_eq!(eval("2r3 / 4"), rational(1, 6));
+        assert_eq!(eval("(2r3) ^ 3"), rational(8, 27));
+        assert_eq!(eval("(2r3) ** -2"), rational(9, 4));
+        assert_eq!(eval("numerator(6r4) + denominator(6r4)"), Ok(Number::Integer(5)));
+        assert_eq!(eval("rational(0.25)"), rational(1, 4));
+        assert_eq!(eval("round(5r2) + floor(-1r2) + truncate(-7r2)"), Ok(Number::Integer(-1)));
+        assert_eq!(eval("1r2 + 0.25"), Ok(Number::Float(0.75)));
+        assert_eq!(eval("-(1r3)"), rational(-1, 3));
+        assert_eq!(eval("max(1r3, 1r2)"), rational(1, 2));
+    }
+
+    #[test]
+    fn test_compare() {
+        let subs = Substitution::new();
+        let one = Term::Number(Number::Integer(1));
+        let one_float = Term::Number(Number::Float(1.0));
+        let two = Term::Number(Number::Integer(2));
+        assert_eq!(compare(&one, &one_float, &subs), Ok(Some(Ordering::Equal)));
+        assert_eq!(compare(&two, &one_float, &subs), Ok(Some(Ordering::Greater)));
+        assert!(compare(&one, &Term::Variable("X".to_string()), &subs).is_err());
+
+        let huge = Term::Number(Number::BigInt("9007199254740993".parse().unwrap()));
+        let huge_float = Term::Number(Number::Float(9007199254740992.0));
+        let third = Term::Number(Number::Rational(BigRational::new(1.into(), 3.into())));
+        assert_eq!(compare(&huge, &huge_float, &subs), Ok(Some(Ordering::Greater)));
+        assert_eq!(compare(&third, &one, &subs), Ok(Some(Ordering::Less)));
+        assert_eq!(compare(&third, &Term::Number(Number::Float(0.3)), &subs), Ok(Some(Ordering::Greater)));
+    }
+}
@@ -0,0 +1,622 @@
+// The clause store: the clauses of a program grouped by predicate, changed at runtime by assert and retract
+use crate::error::PrologError;
+use crate::operators::Operators;
+use crate::parser::DoubleQuotes;
+use crate::tokenizer::{Atom, Number, Statement, Term};
+use crate::unificator::Substitution;
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::rc::{Rc, Weak};
+
+// Predicates with fewer clauses are simply scanned
+const MIN_INDEXED_CLAUSES: usize = 8;
+
+#[derive(Debug)]
+pub struct Clause {
+    pub head: Atom,
+    pub body: Vec<Atom>,
+}
+
+// A clause together with the generations of the database it exists in. Retracting a clause ends its
+// lifetime; it is only removed once no running call can still reach it.
+#[derive(Debug, Clone)]
+struct StoredClause {
+    clause: Rc<Clause>,
+    born: u64,
+    erased: Option<u64>,
+}
+
+impl StoredClause {
+    // True if the clause exists in the database as it was at `generation`
+    fn visible(&self, generation: u64) -> bool {
+        self.born <= generation && self.erased.is_none_or(|erased| erased > generation)
+    }
+}
+
+// The principal functor of an argument, by which the clauses of a predicate are indexed. Numbers are keyed
+// on their value, floats on their bits, which are equal exactly when the floats unify.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Atom(String),
+    Integer(i64),
+    BigInt(BigInt),
+    Rational(BigRational),
+    Float(u64),
+    String(String),
+    Functor(String, usize),
+}
+
+impl Key {
+    // None for a variable, which matches every key
+    fn of(term: &Term) -> Option<Key> {
+        match term {
+            Term::Variable(_) => None,
+            Term::Constant(name) => Some(Key::Atom(name.clone())),
+            Term::Number(Number::Integer(n)) => Some(Key::Integer(*n)),
+            Term::Number(Number::BigInt(n)) => Some(Key::BigInt(n.clone())),
+            Term::Number(Number::Rational(r)) => Some(Key::Rational(r.clone())),
+            Term::Number(Number::Float(f)) => Some(Key::Float(f.to_bits())),
+            Term::String(text) => Some(Key::String(text.clone())),
+            Term::Compound { name, args } => Some(Key::Functor(name.clone(), args.len())),
+        }
+    }
+}
+
+// A hash index on one argument of a predicate. For every key it holds the positions of the clauses whose
+// argument has that key or is a variable; calls with a key no clause has only get the variable ones.
+#[derive(Debug, Default)]
+struct ArgumentIndex {
+    by_key: HashMap<Key, BTreeSet<i64>>,
+    unkeyed: BTreeSet<i64>,
+}
+
+impl ArgumentIndex {
+    fn add(&mut self, key: Option<Key>, position: i64) {
+        match key {
+            Some(key) => {
+                self.by_key.entry(key).or_insert_with(|| self.unkeyed.clone()).insert(position);
+            }
+            None => {
+                self.by_key.values_mut().for_each(|positions| {
+                    positions.insert(position);
+                });
+                self.unkeyed.insert(position);
+            }
+        }
+    }
+
+    fn remove(&mut self, key: Option<Key>, position: i64) {
+        match key {
+            Some(key) => {
+                if let Some(positions) = self.by_key.get_mut(&key) {
+                    positions.remove(&position);
+                }
+            }
+            None => {
+                self.by_key.values_mut().for_each(|positions| {
+                    positions.remove(&position);
+                });
+                self.unkeyed.remove(&position);
+            }
+        }
+    }
+
+    fn candidates(&self, key: &Key) -> &BTreeSet<i64> {
+        self.by_key.get(key).unwrap_or(&self.unkeyed)
+    }
+
+    // An index only narrows calls down if the clauses do not all have the same key
+    fn is_selective(&self) -> bool {
+        self.by_key.len() > 1
+    }
+}
+
+// The clauses of one predicate by position. Every clause keeps its position for good, so that a call can
+// resume after the clause it tried last; asserta/1 adds clauses at positions below the first one.
+#[derive(Debug, Default)]
+struct Predicate {
+    clauses: BTreeMap<i64, StoredClause>,
+    first: i64,  // lowest position used so far
+    end: i64,    // position of the next clause added at the end
+    live: usize, // clauses that are not retracted
+    dynamic: bool,
+    indexes: HashMap<usize, ArgumentIndex>, // by argument, built by the first call that can use them
+    unselective: HashMap<usize, usize>,     // arguments not worth indexing, with the number of clauses then
+    erased: VecDeque<(u64, i64)>,           // retracted clauses still in `clauses`, by generation and position
+    calls: Vec<(u64, Weak<()>)>,            // generations of the calls that may be running, oldest first
+}
+
+impl Predicate {
+    // True if calling the predicate is not an existence error
+    fn is_defined(&self) -> bool {
+        self.dynamic || self.live > 0
+    }
+
+    fn at(&self, position: i64) -> &StoredClause {
+        &self.clauses[&position]
+    }
+
+    fn push(&mut self, clause: StoredClause, at_start: bool) {
+        let position = if at_start {
+            self.first -= 1;
+            self.first
+        } else {
+            self.end += 1;
+            self.end - 1
+        };
+        for (&arg, index) in &mut self.indexes {
+            index.add(Key::of(&clause.clause.head.args[arg]), position);
+        }
+        self.clauses.insert(position, clause);
+        self.live += 1;
+    }
+
+    // Ends the lifetime of the clause at `position` unless it is already retracted
+    fn erase(&mut self, position: i64, generation: u64) {
+        if let Some(stored) = self.clauses.get_mut(&position).filter(|stored| stored.erased.is_none()) {
+            stored.erased = Some(generation);
+            self.erased.push_back((generation, position));
+            self.live -= 1;
+        }
+    }
+
+    // Removes the retracted clauses that no running call can see any more, i.e. those retracted no later than
+    // the oldest call still running started
+    fn collect_erased(&mut self) {
+        if self.erased.is_empty() {
+            return;
+        }
+        self.calls.retain(|(_, call)| call.strong_count() > 0);
+        let oldest = self.calls.first().map_or(u64::MAX, |(generation, _)| *generation);
+        while let Some(&(_, position)) = self.erased.front().filter(|(erased, _)| *erased <= oldest) {
+            self.erased.pop_front();
+            let stored = self.clauses.remove(&position).expect("a retracted clause is kept until it is removed");
+            for (&arg, index) in &mut self.indexes {
+                index.remove(Key::of(&stored.clause.head.args[arg]), position);
+            }
+        }
+    }
+
+    // The argument whose index to look a call up in, given the keys of the call's arguments. The first argument
+    // is indexed when it is bound; when it is not, or its index does not tell the clauses apart, the next bound
+    // argument is indexed just in time. Indexes are kept up to date from then on. An index that does not tell
+    // the clauses apart is dropped, and not tried again until the predicate has twice as many clauses.
+    fn select_index(&mut self, keys: &[Option<Key>]) -> Option<usize> {
+        if self.clauses.len() < MIN_INDEXED_CLAUSES {
+            return None;
+        }
+        for arg in keys.iter().enumerate().filter(|(_, key)| key.is_some()).map(|(arg, _)| arg) {
+            if self.indexes.contains_key(&arg) {
+                return Some(arg);
+            }
+            if self.unselective.get(&arg).is_some_and(|&clauses| self.clauses.len() < 2 * clauses) {
+                continue;
+            }
+            let mut index = ArgumentIndex::default();
+            for (&position, stored) in &self.clauses {
+                index.add(Key::of(&stored.clause.head.args[arg]), position);
+            }
+            if index.is_selective() {
+                self.unselective.remove(&arg);
+                self.indexes.insert(arg, index);
+                return Some(arg);
+            }
+            self.unselective.insert(arg, self.clauses.len());
+        }
+        None
+    }
+}
+
+#[derive(Debug, Default)]
+struct Store {
+    predicates: HashMap<(String, usize), Predicate>,
+    generation: u64, // incremented by every change
+    operators: Operators, // the operator table, extended by op/3
+    double_quotes: DoubleQuotes, // the double_quotes flag, set by set_prolog_flag/2
+}
+
+/// Where the search for the clauses of a call resumes: the position of the next clause to try and the
+/// generation of the database the call sees. Following the logical update view, a call only ever sees the
+/// clauses that existed when it started, whatever is asserted or retracted while it runs. The retracted
+/// clauses it could still see are kept for as long as a copy of the cursor exists.
+#[derive(Debug, Clone)]
+pub struct ClauseCursor {
+    pub position: i64,
+    pub generation: u64,
+    _call: Rc<()>, // only held
+}
+
+impl ClauseCursor {
+    /// The cursor of the same call after the clause at `position`
+    pub fn after(&self, position: i64) -> ClauseCursor {
+        ClauseCursor { position: position + 1, ..self.clone() }
+    }
+}
+
+/// The database a query runs against. Clones are handles to the same clauses, so a change made by one
+/// derivation is seen by the others, e.g. an assert inside findall/3.
+#[derive(Debug, Clone, Default)]
+pub struct ClauseStore {
+    store: Rc<RefCell<Store>>,
+}
+
+impl From<Vec<Statement>> for ClauseStore {
+    fn from(program: Vec<Statement>) -> ClauseStore {
+        let db = ClauseStore::default();
+        program.into_iter().for_each(|stmt| db.add(stmt));
+        db
+    }
+}
+
+impl From<&[Statement]> for ClauseStore {
+    fn from(program: &[Statement]) -> ClauseStore {
+        ClauseStore::from(program.to_vec())
+    }
+}
+
+impl From<Rc<[Statement]>> for ClauseStore {
+    fn from(program: Rc<[Statement]>) -> ClauseStore {
+        ClauseStore::from(&program[..])
+    }
+}
+
+impl ClauseStore {
+    /// A cursor at the first clause of a call to the predicate of `goal` that starts now
+    pub fn start(&self, goal: &Atom) -> ClauseCursor {
+        let mut store = self.store.borrow_mut();
+        let generation = store.generation;
+        let Some(predicate) = store.predicates.get_mut(&(goal.name.clone(), goal.args.len())) else {
+            return ClauseCursor { position: i64::MIN, generation, _call: Rc::new(()) };
+        };
+        predicate.collect_erased();
+        while predicate.calls.last().is_some_and(|(_, call)| call.strong_count() == 0) {
+            predicate.calls.pop();
+        }
+        // calls that start in the same generation share their entry
+        let call = match predicate.calls.last() {
+            Some((started, call)) if *started == generation => call.upgrade(),
+            _ => None,
+        };
+        let call = call.unwrap_or_else(|| {
+            let call = Rc::new(());
+            predicate.calls.push((generation, Rc::downgrade(&call)));
+            call
+        });
+        ClauseCursor { position: i64::MIN, generation, _call: call }
+    }
+
+    /// Adds a clause of the program being loaded at the end of its predicate; queries and directives are ignored
+    pub fn add(&self, stmt: Statement) {
+        let (head, body) = match stmt {
+            Statement::Fact(head) => (head, vec![]),
+            Statement::Rule { head, body } => (head, body),
+            Statement::Query { .. } | Statement::Directive { .. } => return,
+        };
+        let mut store = self.store.borrow_mut();
+        store.generation += 1;
+        let born = store.generation;
+        let predicate = store.predicates.entry((head.name.clone(), head.args.len())).or_default();
+        predicate.push(StoredClause { clause: Rc::new(Clause { head, body }), born, erased: None }, false);
+    }
+
+    /// True if the predicate has clauses or is dynamic, so that calling it is not an existence error
+    pub fn is_defined(&self, name: &str, arity: usize) -> bool {
+        let store = self.store.borrow();
+        store.predicates.get(&(name.to_string(), arity)).is_some_and(Predicate::is_defined)
+    }
+
+    /// The first clause at or after the cursor that is visible to the call and may match `goal` under `subs`,
+    /// with its position. Clauses whose arguments have a different principal functor than the bound arguments
+    /// of the goal are skipped, using an index on large predicates.
+    pub fn next_clause(&self, goal: &Atom, subs: &Substitution, cursor: &ClauseCursor) -> Option<(i64, Rc<Clause>)> {
+        let mut store = self.store.borrow_mut();
+        let predicate = store.predicates.get_mut(&(goal.name.clone(), goal.args.len()))?;
+        let args: Vec<&Term> = goal.args.iter().map(|arg| deref(arg, subs)).collect();
+        let keys: Vec<Option<Key>> = args.iter().map(|arg| Key::of(arg)).collect();
+        let candidate = |stored: &StoredClause| {
+            stored.visible(cursor.generation)
+                && stored.clause.head.args.iter().zip(&args).all(|(arg, call)| same_principal_functor(arg, call))
+        };
+
+        let position = match predicate.select_index(&keys) {
+            Some(arg) => {
+                let positions = predicate.indexes[&arg].candidates(keys[arg].as_ref()?);
+                positions.range(cursor.position..).copied().find(|&position| candidate(predicate.at(position)))
+            }
+            None => predicate
+                .clauses
+                .range(cursor.position..)
+                .find(|(_, stored)| candidate(stored))
+                .map(|(&position, _)| position),
+        }?;
+        Some((position, Rc::clone(&predicate.at(position).clause)))
+    }
+
+    /// Declares a predicate dynamic, so that it can be changed and calling it without clauses simply fails
+    pub fn declare_dynamic(&self, name: &str, arity: usize) -> Result<(), PrologError> {
+        let mut store = self.store.borrow_mut();
+        let predicate = store.predicates.entry((name.to_string(), arity)).or_default();
+        if !predicate.dynamic && predicate.is_defined() {
+            return Err(static_procedure(name, arity));
+        }
+        predicate.dynamic = true;
+        Ok(())
+    }
+
+    /// Adds a clause at the start (asserta/1) or the end (assertz/1) of its predicate. A predicate that is not
+    /// defined yet becomes dynamic; the clauses of a static predicate cannot be changed.
+    pub fn assert(&self, head: Atom, body: Vec<Atom>, at_start: bool) -> Result<(), PrologError> {
+        let mut store = self.store.borrow_mut();
+        store.generation += 1;
+        let born = store.generation;
+        let (name, arity) = (head.name.clone(), head.args.len());
+        let predicate = store.predicates.entry((name.clone(), arity)).or_default();
+        if !predicate.dynamic && predicate.is_defined() {
+            return Err(static_procedure(&name, arity));
+        }
+        predicate.dynamic = true;
+        predicate.collect_erased();
+        predicate.push(StoredClause { clause: Rc::new(Clause { head, body }), born, erased: None }, at_start);
+        Ok(())
+    }
+
+    /// Checks that the clauses of a predicate may be removed, i.e. that it is dynamic or unknown
+    pub fn check_modifiable(&self, name: &str, arity: usize) -> Result<(), PrologError> {
+        let store = self.store.borrow();
+        match store.predicates.get(&(name.to_string(), arity)) {
+            Some(predicate) if !predicate.dynamic && predicate.is_defined() => Err(static_procedure(name, arity)),
+            _ => Ok(()),
+        }
+    }
+
+    /// The operators the program declared so far, for reading queries and writing their answers
+    pub fn operators(&self) -> Operators {
+        self.store.borrow().operators.clone()
+    }
+
+    /// op/3: declares operators for the rest of the program and the queries that follow
+    pub fn op(&self, priority: &Term, specifier: &Term, names: &Term) -> Result<(), PrologError> {
+        self.store.borrow_mut().operators.op(priority, specifier, names)
+    }
+
+    /// How the queries that follow read double-quoted strings
+    pub fn double_quotes(&self) -> DoubleQuotes {
+        self.store.borrow().double_quotes
+    }
+
+    pub fn set_double_quotes(&self, double_quotes: DoubleQuotes) {
+        self.store.borrow_mut().double_quotes = double_quotes;
+    }
+
+    /// Ends the lifetime of the clause at `position`; calls that started earlier still see it
+    pub fn retract(&self, name: &str, arity: usize, position: i64) {
+        let mut store = self.store.borrow_mut();
+        store.generation += 1;
+        let generation = store.generation;
+        if let Some(predicate) = store.predicates.get_mut(&(name.to_string(), arity)) {
+            predicate.erase(position, generation);
+            predicate.collect_erased();
+        }
+    }
+
+    /// Removes every clause of a dynamic predicate together with its dynamic declaration
+    pub fn abolish(&self, name: &str, arity: usize) -> Result<(), PrologError> {
+        self.check_modifiable(name, arity)?;
+        let mut store = self.store.borrow_mut();
+        store.generation += 1;
+        let generation = store.generation;
+        if let Some(predicate) = store.predicates.get_mut(&(name.to_string(), arity)) {
+            let positions: Vec<i64> = predicate.clauses.keys().copied().collect();
+            positions.into_iter().for_each(|position| predicate.erase(position, generation));
+            predicate.collect_erased();
+            predicate.dynamic = false;
+        }
+        Ok(())
+    }
+}
+
+// Follows the bindings of a variable down to the term it stands for
+fn deref<'a>(mut term: &'a Term, subs: &'a Substitution) -> &'a Term {
+    while let Term::Variable(v) = term {
+        match subs.get(v) {
+            Some(bound) => term = bound,
+            None => break,
+        }
+    }
+    term
+}
+
+// False if the two terms cannot unify because their principal functors differ
+fn same_principal_functor(t1: &Term, t2: &Term) -> bool {
+    match (t1, t2) {
+        (Term::Variable(_), _) | (_, Term::Variable(_)) => true,
+        (Term::Constant(a), Term::Constant(b)) | (Term::String(a), Term::String(b)) => a == b,
+        (Term::Number(a), Term::Number(b)) => a == b,
+        (Term::Compound { name: n1, args: a1 }, Term::Compound { name: n2, args: a2 }) => {
+            n1 == n2 && a1.len() == a2.len()
+        }
+        _ => false,
+    }
+}
+
+fn static_procedure(name: &str, arity: usize) -> PrologError {
+    PrologError::permission_error("modify", "static_procedure", format!("{}/{}", name, arity))
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::unificator::format_term;
+
+    fn store(src: &str) -> ClauseStore { // helper
+        let (stmts, errors) = Parser::from_source(src).unwrap().parse_program();
+        assert!(errors.is_empty());
+        stmts.into()
+    }
+
+    // the heads a call that started with `cursor`, or starts now, can use
+    fn heads(db: &ClauseStore, goal: &Atom, cursor: Option<&ClauseCursor>) -> Vec<String> { // helper
+        let mut cursor = cursor.cloned().unwrap_or_else(|| db.start(goal));
+        let mut heads = vec![];
+        while let Some((position, clause)) = db.next_clause(goal, &Substitution::new(), &cursor) {
+            heads.push(clause.head.args.iter().map(format_term).collect::<Vec<_>>().join(","));
+            cursor = cursor.after(position);
+        }
+        heads
+    }
+
+    fn goal(src: &str) -> Atom { // helper
+        match Parser::from_source(&format!("?- {}.", src)).unwrap().parse_program().0.pop() {
+            Some(Statement::Query { mut body, .. }) => body.remove(0),
+            _ => panic!("not a query: {}", src),
+        }
+    }
+
+    fn indexed_arguments(db: &ClauseStore, name: &str, arity: usize) -> Vec<usize> { // helper
+        let store = db.store.borrow();
+        let mut args: Vec<usize> = store.predicates[&(name.to_string(), arity)].indexes.keys().copied().collect();
+        args.sort();
+        args
+    }
+
+    #[test]
+    fn test_logical_update_view() {
+        let db = store("q(x). q(y).");
+        let r = |arg: &str| Atom::new("r", vec![Term::Constant(arg.to_string())]);
+        let goal = Atom::new("r", vec![Term::Variable("X".to_string())]);
+        db.declare_dynamic("r", 1).unwrap();
+        assert!(db.is_defined("r", 1) && !db.is_defined("r", 2));
+        db.assert(r("a"), vec![], false).unwrap();
+        db.assert(r("b"), vec![], false).unwrap();
+        db.assert(r("z"), vec![], true).unwrap();
+        assert_eq!(heads(&db, &goal, None), vec!["z", "a", "b"]);
+
+        // a call that started before a change does not see it
+        let before = db.start(&goal);
+        let subs = Substitution::new();
+        let (position, _) = db.next_clause(&goal, &subs, &before).unwrap();
+        let (position, _) = db.next_clause(&goal, &subs, &before.after(position)).unwrap();
+        db.retract("r", 1, position);
+        db.assert(r("c"), vec![], false).unwrap();
+        assert_eq!(heads(&db, &goal, Some(&before)), vec!["z", "a", "b"]);
+        assert_eq!(heads(&db, &goal, None), vec!["z", "b", "c"]);
+
+        db.abolish("r", 1).unwrap();
+        assert!(!db.is_defined("r", 1));
+        assert_eq!(heads(&db, &goal, None), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_retracted_clauses_are_removed() {
+        let db = ClauseStore::default();
+        let call = goal("c(X)");
+        let stored = || db.store.borrow().predicates[&("c".to_string(), 1)].clauses.len();
+        db.assert(goal("c(0)"), vec![], false).unwrap();
+        let (position, _) = db.next_clause(&call, &Substitution::new(), &db.start(&call)).unwrap();
+        db.retract("c", 1, position);
+        db.assert(goal("c(1)"), vec![], false).unwrap();
+        assert_eq!(stored(), 1);
+
+        // a running call keeps the clauses it can see until it is done
+        let running = db.start(&call);
+        let (position, _) = db.next_clause(&call, &Substitution::new(), &running).unwrap();
+        db.retract("c", 1, position);
+        db.assert(goal("c(2)"), vec![], false).unwrap();
+        assert_eq!(stored(), 2);
+        assert_eq!(heads(&db, &call, Some(&running)), vec!["1"]);
+        drop(running);
+        assert_eq!(heads(&db, &call, None), vec!["2"]);
+        assert_eq!(stored(), 1);
+        assert!(db.is_defined("c", 1));
+    }
+
+    #[test]
+    fn test_static_procedures() {
+        let db = store("q(x). q(y).");
+        let error = "Permission error: cannot modify static_procedure q/1";
+        assert_eq!(db.assert(Atom::new("q", vec![Term::Constant("z".to_string())]), vec![], false).unwrap_err().to_string(), error);
+        assert_eq!(db.declare_dynamic("q", 1).unwrap_err().to_string(), error);
+        assert_eq!(db.abolish("q", 1).unwrap_err().to_string(), error);
+        assert!(db.check_modifiable("q", 2).is_ok());
+        // a predicate that is not defined yet becomes dynamic when a clause is asserted
+        db.assert(Atom::new("q", vec![]), vec![], false).unwrap();
+        assert!(db.check_modifiable("q", 0).is_ok());
+    }
+
+    #[test]
+    fn test_numeric_keys() {
+        let db = store("n(1, a). n(1.0, b). n(-0.0, c). n(0.0, d). n(1r3, e). n(100000000000000000000, f). n(X, g). n(2, h).");
+        assert_eq!(heads(&db, &goal("n(1, Y)"), None), vec!["1,a", "X,g"]);
+        assert_eq!(indexed_arguments(&db, "n", 2), vec![0]);
+        // integers and floats with the same value do not unify, and neither do 0.0 and -0.0
+        assert_eq!(heads(&db, &goal("n(1.0, Y)"), None), vec!["1.0,b", "X,g"]);
+        assert_eq!(heads(&db, &goal("n(0.0, Y)"), None), vec!["0.0,d", "X,g"]);
+        assert_eq!(heads(&db, &goal("n(1r3, Y)"), None), vec!["1r3,e", "X,g"]);
+        assert_eq!(heads(&db, &goal("n(100000000000000000000, Y)"), None), vec!["100000000000000000000,f", "X,g"]);
+    }
+
+    #[test]
+    fn test_first_argument_index() {
+        let db = store("p(1, a). p(2, b). p(f(x), c). p(X, d). p(3, e). p(f(y), a). p(g(z), b). p([s], c). p(1, f).");
+        // a call gets the clauses with the same principal functor in its first argument or a variable there
+        assert_eq!(heads(&db, &goal("p(1, Y)"), None), vec!["1,a", "X,d", "1,f"]);
+        assert_eq!(indexed_arguments(&db, "p", 2), vec![0]);
+        assert_eq!(heads(&db, &goal("p(f(_), Y)"), None), vec!["f(x),c", "X,d", "f(y),a"]);
+        assert_eq!(heads(&db, &goal("p([_|_], Y)"), None), vec!["X,d", "[s],c"]);
+        assert_eq!(heads(&db, &goal("p(4, Y)"), None), vec!["X,d"]);
+        assert_eq!(heads(&db, &goal("p(X, Y)"), None).len(), 9);
+
+        // the index follows the clauses that are added and removed
+        db.declare_dynamic("q", 2).unwrap();
+        for clause in ["q(1, a)", "q(2, b)", "q(3, c)", "q(4, d)", "q(5, e)", "q(6, f)", "q(7, g)", "q(8, h)"] {
+            let head = goal(clause);
+            db.assert(head, vec![], false).unwrap();
+        }
+        assert_eq!(heads(&db, &goal("q(2, Y)"), None), vec!["2,b"]);
+        db.assert(goal("q(2, z)"), vec![], true).unwrap();
+        db.assert(goal("q(X, y)"), vec![], false).unwrap();
+        db.assert(goal("q(2, x)"), vec![], false).unwrap();
+        let before = db.start(&goal("q(2, b)"));
+        let (position, _) = db.next_clause(&goal("q(2, b)"), &Substitution::new(), &before).unwrap();
+        db.retract("q", 2, position);
+        assert_eq!(heads(&db, &goal("q(2, Y)"), Some(&before)), vec!["2,z", "2,b", "X,y", "2,x"]);
+        assert_eq!(heads(&db, &goal("q(2, Y)"), None), vec!["2,z", "X,y", "2,x"]);
+        assert_eq!(heads(&db, &goal("q(9, Y)"), None), vec!["X,y"]);
+    }
+
+    #[test]
+    fn test_just_in_time_indexes() {
+        let db = store("r(k, 1). r(k, 2). r(k, 3). r(k, 4). r(k, 5). r(k, 6). r(k, 7). r(k, 8). r(X, 3).");
+        // a first argument that is the same in every clause does not tell them apart, so the second one is indexed
+        assert_eq!(heads(&db, &goal("r(k, 3)"), None), vec!["k,3", "X,3"]);
+        assert_eq!(indexed_arguments(&db, "r", 2), vec![1]);
+        assert_eq!(heads(&db, &goal("r(X, 5)"), None), vec!["k,5"]);
+        // the first argument is tried again once the predicate has grown enough
+        db.declare_dynamic("t", 2).unwrap();
+        for i in 0..9 {
+            db.assert(goal(&format!("t(k, {})", i)), vec![], false).unwrap();
+        }
+        assert_eq!(heads(&db, &goal("t(k, 3)"), None), vec!["k,3"]);
+        assert_eq!(indexed_arguments(&db, "t", 2), vec![1]);
+        for i in 0..9 {
+            db.assert(goal(&format!("t(j{}, x)", i)), vec![], false).unwrap();
+        }
+        assert_eq!(heads(&db, &goal("t(j3, x)"), None), vec!["j3,x"]);
+        assert_eq!(indexed_arguments(&db, "t", 2), vec![0, 1]);
+
+        // an unbound first argument is not indexed at all
+        let db = store("s(a, 1). s(b, 2). s(c, 3). s(d, 4). s(e, 5). s(f, 6). s(g, 7). s(h, 8).");
+        assert_eq!(heads(&db, &goal("s(X, 7)"), None), vec!["g,7"]);
+        assert_eq!(indexed_arguments(&db, "s", 2), vec![1]);
+        // the substitution of the call is taken into account
+        let subs = Substitution::unit("X".to_string(), Term::Constant("c".to_string()));
+        let call = goal("s(X, Y)");
+        let (_, clause) = db.next_clause(&call, &subs, &db.start(&call)).unwrap();
+        assert_eq!(format_term(&clause.head.args[1]), "3");
+        assert_eq!(indexed_arguments(&db, "s", 2), vec![0, 1]);
+    }
+}
@@ -0,0 +1,128 @@
+// Errors raised while reading and running Prolog programs
+use crate::tokenizer::Span;
+use std::fmt;
+
+/// An error from any stage of the interpreter, classified like the ISO error terms.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PrologError {
+    /// Text that cannot be split into tokens, e.g. an unknown character
+    Lexical(SourceError),
+    /// Tokens that do not form a clause or query
+    Syntax(SourceError),
+    /// An argument that should be bound is an unbound variable
+    Instantiation,
+    /// An argument of the wrong type, e.g. an atom where an integer is expected
+    Type { expected: String, culprit: String },
+    /// An argument of the right type but outside the allowed values, e.g. a negative length
+    Domain { domain: String, culprit: String },
+    /// A reference to something that does not exist, e.g. an unknown procedure
+    Existence { kind: String, culprit: String },
+    /// An action that is not allowed on the culprit, e.g. redefining the ',' operator
+    Permission { action: String, kind: String, culprit: String },
+    /// An arithmetic error such as zero_divisor or undefined
+    Evaluation(String),
+    /// A computation that would exceed the available resources
+    Resource(String),
+}
+
+/// The place of a lexical or syntax error in the source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceError {
+    pub message: String,
+    pub span: Span,
+    pub line: Option<String>, // the source line containing the span, when the source is known
+}
+
+impl PrologError {
+    pub fn type_error(expected: &str, culprit: impl fmt::Display) -> PrologError {
+        PrologError::Type { expected: expected.to_string(), culprit: culprit.to_string() }
+    }
+
+    pub fn domain_error(domain: &str, culprit: impl fmt::Display) -> PrologError {
+        PrologError::Domain { domain: domain.to_string(), culprit: culprit.to_string() }
+    }
+
+    pub fn existence_error(kind: &str, culprit: impl fmt::Display) -> PrologError {
+        PrologError::Existence { kind: kind.to_string(), culprit: culprit.to_string() }
+    }
+
+    pub fn permission_error(action: &str, kind: &str, culprit: impl fmt::Display) -> PrologError {
+        PrologError::Permission { action: action.to_string(), kind: kind.to_string(), culprit: culprit.to_string() }
+    }
+
+    pub fn evaluation_error(error: &str) -> PrologError {
+        PrologError::Evaluation(error.to_string())
+    }
+
+    pub fn resource_error(resource: &str) -> PrologError {
+        PrologError::Resource(resource.to_string())
+    }
+
+    /// The source position of a lexical or syntax error
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            PrologError::Lexical(error) | PrologError::Syntax(error) => Some(error.span),
+            _ => None,
+        }
+    }
+}
+
+impl SourceError {
+    // Builds an error at `span`, quoting its line from `source` if there is one
+    pub fn new(message: impl Into<String>, span: Span, source: Option<&str>) -> SourceError {
+        let line = source.map(|source| source.lines().nth(span.line.saturating_sub(1)).unwrap_or("").to_string());
+        SourceError { message: message.into(), span, line }
+    }
+}
+
+// "line:column: message", followed by the source line and a caret under the span when it is known
+impl fmt::Display for SourceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.span.line, self.span.column, self.message)?;
+        if let Some(text) = &self.line {
+            let column = self.span.column.max(1);
+            let width = (self.span.end - self.span.start).min(text.chars().count().saturating_sub(column - 1)).max(1);
+            write!(f, "\n{}\n{}{}", text, " ".repeat(column - 1), "^".repeat(width))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for PrologError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PrologError::Lexical(error) => write!(f, "Lexical error: {}", error),
+            PrologError::Syntax(error) => write!(f, "Syntax error: {}", error),
+            PrologError::Instantiation => write!(f, "Instantiation error"),
+            PrologError::Type { expected, culprit } => write!(f, "Type error: {} expected, found {}", expected, culprit),
+            PrologError::Domain { domain, culprit } => write!(f, "Domain error: {} expected, found {}", domain, culprit),
+            PrologError::Existence { kind, culprit } => write!(f, "Existence error: unknown {} {}", kind, culprit),
+            PrologError::Permission { action, kind, culprit } => {
+                write!(f, "Permission error: cannot {} {} {}", action, kind, culprit)
+            }
+            PrologError::Evaluation(error) => write!(f, "Evaluation error: {}", error),
+            PrologError::Resource(resource) => write!(f, "Resource error: {}", resource),
+        }
+    }
+}
+
+impl std::error::Error for PrologError {}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let span = Span { start: 5, end: 8, line: 2, column: 3 };
+        let error = PrologError::Syntax(SourceError::new("Expected term", span, Some("p.\nq(abc\n")));
+        assert_eq!(error.to_string(), "Syntax error: 2:3: Expected term\nq(abc\n  ^^^");
+        assert_eq!(SourceError::new("Expected term", span, None).to_string(), "2:3: Expected term");
+        assert_eq!(error.span(), Some(span));
+
+        assert_eq!(PrologError::type_error("integer", "a").to_string(), "Type error: integer expected, found a");
+        assert_eq!(PrologError::existence_error("procedure", "foo/1").to_string(), "Existence error: unknown procedure foo/1");
+        assert_eq!(PrologError::evaluation_error("zero_divisor").span(), None);
+    }
+}
@@ -0,0 +1,125 @@
+// The library of predicates written in Prolog, consulted before the user's program
+use crate::parser::Parser;
+use crate::tokenizer::Statement;
+use std::collections::HashSet;
+use std::sync::OnceLock;
+
+const LISTS: &str = include_str!("library/lists.pl");
+const APPLY: &str = include_str!("library/apply.pl");
+
+// The libraries by name, as in use_module(library(lists))
+const LIBRARIES: &[(&str, &str)] = &[("lists", LISTS), ("apply", APPLY)];
+
+// True if `name` is one of the libraries, which are always loaded
+pub fn is_library(name: &str) -> bool {
+    LIBRARIES.iter().any(|&(library, _)| library == name)
+}
+
+fn library() -> &'static [Statement] {
+    static LIBRARY: OnceLock<Vec<Statement>> = OnceLock::new();
+    LIBRARY.get_or_init(|| {
+        LIBRARIES
+            .iter()
+            .copied()
+            .flat_map(|(name, source)| {
+                let (clauses, errors) = Parser::from_source(source)
+                    .map(|mut parser| parser.parse_program())
+                    .unwrap_or_else(|e| panic!("the {} library does not tokenize: {}", name, e));
+                assert!(errors.is_empty(), "the {} library does not parse: {:?}", name, errors);
+                clauses
+            })
+            .collect()
+    })
+}
+
+// Name and arity of the predicate a clause belongs to
+fn predicate(stmt: &Statement) -> Option<(&str, usize)> {
+    match stmt {
+        Statement::Fact(head) | Statement::Rule { head, .. } => Some((head.name.as_str(), head.args.len())),
+        Statement::Query { .. } | Statement::Directive { .. } => None,
+    }
+}
+
+// Puts the library clauses in front of the program. A predicate the program defines itself
+// replaces the library version instead of adding clauses to it.
+pub fn with_library(program: Vec<Statement>) -> Vec<Statement> {
+    let defined: HashSet<(&str, usize)> = program.iter().filter_map(predicate).collect();
+    let mut clauses: Vec<Statement> = library()
+        .iter()
+        .filter(|stmt| predicate(stmt).is_none_or(|key| !defined.contains(&key)))
+        .cloned()
+        .collect();
+    clauses.extend(program);
+    clauses
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_query;
+    use crate::tokenizer::tokenize;
+    use crate::solver::solve;
+    use crate::unificator::format_answer;
+
+    fn answers(program: &str, query: &str) -> Vec<String> { // helper
+        let (program, _) = Parser::new(tokenize(program).unwrap()).parse_program();
+        let query = parse_query(tokenize(query).unwrap()).unwrap();
+        solve(&query, with_library(program))
+            .map(|answer| answer.map_or_else(|e| e.to_string(), |subs| format_answer(&subs)))
+            .collect()
+    }
+
+    #[test]
+    fn test_list_library() {
+        assert_eq!(answers("", "?- append(X, [c], [a, b, c])."), vec!["X = [a,b]"]);
+        assert_eq!(answers("", "?- member(X, [a, b])."), vec!["X = a", "X = b"]);
+        assert_eq!(answers("", "?- memberchk(X, [a, b])."), vec!["X = a"]);
+        assert_eq!(answers("", "?- reverse([1, 2, 3], R)."), vec!["R = [3,2,1]"]);
+        assert_eq!(answers("", "?- nth0(1, [a, b, c], E), nth1(1, [a, b, c], F)."), vec!["E = b, F = a"]);
+        assert_eq!(answers("", "?- nth1(I, [a, b], E)."), vec!["E = a, I = 1", "E = b, I = 2"]);
+        assert_eq!(answers("", "?- last([a, b, c], X), sum_list([1, 2, 3], S)."), vec!["S = 6, X = c"]);
+        assert_eq!(answers("", "?- sumlist([1, 2, 3], S)."), vec!["S = 6"]);
+        assert_eq!(answers("", "?- select(b, [a, b, c], R)."), vec!["R = [a,c]"]);
+    }
+
+    #[test]
+    fn test_apply_library() {
+        let program = "double(X, Y) :- Y is 2 * X.\nadd(X, Y, Z) :- Z is X + Y.\nsmall(X) :- X < 3.";
+        assert_eq!(answers(program, "?- maplist(double, [1, 2, 3], L)."), vec!["L = [2,4,6]"]);
+        assert_eq!(answers(program, "?- maplist(small, [1, 2]), \\+ maplist(small, [1, 5])."), vec!["true"]);
+        assert_eq!(answers(program, "?- maplist(add, [1, 2], [10, 20], L)."), vec!["L = [11,22]"]);
+        assert_eq!(answers(program, "?- L = [a, a], maplist(=(X), L)."), vec!["L = [a,a], X = a"]);
+        assert_eq!(answers(program, "?- foldl(add, [1, 2, 3], 0, S)."), vec!["S = 6"]);
+        assert_eq!(answers(program, "?- include(small, [1, 4, 2, 5], I), exclude(small, [1, 4, 2, 5], E)."), vec!["E = [4,5], I = [1,2]"]);
+        assert_eq!(answers(program, "?- forall(member(X, [1, 2]), small(X))."), vec!["true"]);
+        assert_eq!(answers(program, "?- forall(member(X, [1, 5]), small(X))."), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_program_overrides_library() {
+        assert_eq!(answers("member(x, L).", "?- member(X, [a, b])."), vec!["X = x"]);
+        // other arities of the same name are kept
+        assert_eq!(answers("reverse(x).", "?- reverse([a, b], R)."), vec!["R = [b,a]"]);
+    }
+
+    #[test]
+    fn test_length() {
+        assert_eq!(answers("", "?- length([a, b, c], N)."), vec!["N = 3"]);
+        assert_eq!(answers("", "?- length([a | T], 3), T = [A, B], var(A), A \\== B."), vec!["T = [A,B]"]);
+        assert_eq!(answers("", "?- length([a, b], 1)."), Vec::<String>::new());
+        assert_eq!(answers("", "?- length(L, N), N >= 2, !, L = [A, B], A \\== B."), vec!["L = [A,B], N = 2"]);
+        assert_eq!(answers("", "?- length(L, -1)."), vec!["Domain error: not_less_than_zero expected, found -1"]);
+        assert_eq!(answers("", "?- length(L, a)."), vec!["Type error: integer expected, found a"]);
+        assert_eq!(answers("", "?- length([a | b], N)."), vec!["Type error: list expected, found [a|b]"]);
+    }
+
+    #[test]
+    fn test_sort() {
+        assert_eq!(answers("", "?- msort([b, 2, a, f(x), 1.0, 1, b], L)."), vec!["L = [1.0,1,2,a,b,b,f(x)]"]);
+        assert_eq!(answers("", "?- sort([c, a, b, a], L)."), vec!["L = [a,b,c]"]);
+        assert_eq!(answers("", "?- sort([g(a, b), f(b), g(a, a)], L)."), vec!["L = [f(b),g(a, a),g(a, b)]"]);
+        assert_eq!(answers("", "?- sort([b | T], L)."), vec!["Instantiation error"]);
+        assert_eq!(answers("", "?- msort(a, L)."), vec!["Type error: list expected, found a"]);
+    }
+}
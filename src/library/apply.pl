@@ -0,0 +1,53 @@
+/* Higher-order predicates consulted before every program, also available through
+   :- use_module(library(apply)). They call their goal arguments with call/N.
+   aggregate_all/3 lives in the solver. */
+
+%! maplist(:Goal, ?List1, ..., ?List6)
+%  Goal holds for the elements at the same position in all lists.
+maplist(G, []).
+maplist(G, [X|Xs]) :- call(G, X), maplist(G, Xs).
+maplist(G, [], []).
+maplist(G, [X|Xs], [Y|Ys]) :- call(G, X, Y), maplist(G, Xs, Ys).
+maplist(G, [], [], []).
+maplist(G, [X|Xs], [Y|Ys], [Z|Zs]) :- call(G, X, Y, Z), maplist(G, Xs, Ys, Zs).
+maplist(G, [], [], [], []).
+maplist(G, [X|Xs], [Y|Ys], [Z|Zs], [V|Vs]) :- call(G, X, Y, Z, V), maplist(G, Xs, Ys, Zs, Vs).
+maplist(G, [], [], [], [], []).
+maplist(G, [X|Xs], [Y|Ys], [Z|Zs], [V|Vs], [W|Ws]) :-
+    call(G, X, Y, Z, V, W), maplist(G, Xs, Ys, Zs, Vs, Ws).
+maplist(G, [], [], [], [], [], []).
+maplist(G, [X|Xs], [Y|Ys], [Z|Zs], [V|Vs], [W|Ws], [U|Us]) :-
+    call(G, X, Y, Z, V, W, U), maplist(G, Xs, Ys, Zs, Vs, Ws, Us).
+
+%! foldl(:Goal, ?List1, ..., ?List3, +V0, -V)
+%  Folds the lists from the left: call(Goal, X1, ..., V0, V1) for the first elements, and so on.
+foldl(G, [], V, V).
+foldl(G, [X|Xs], V0, V) :- call(G, X, V0, V1), foldl(G, Xs, V1, V).
+foldl(G, [], [], V, V).
+foldl(G, [X|Xs], [Y|Ys], V0, V) :- call(G, X, Y, V0, V1), foldl(G, Xs, Ys, V1, V).
+foldl(G, [], [], [], V, V).
+foldl(G, [X|Xs], [Y|Ys], [Z|Zs], V0, V) :- call(G, X, Y, Z, V0, V1), foldl(G, Xs, Ys, Zs, V1, V).
+
+%! include(:Goal, +List, -Included)
+%  Included holds the elements of List for which Goal succeeds.
+include(G, [], []).
+include(G, [X|Xs], Included) :-
+    (   call(G, X)
+    ->  Included = [X|Rest]
+    ;   Included = Rest
+    ),
+    include(G, Xs, Rest).
+
+%! exclude(:Goal, +List, -Excluded)
+%  Excluded holds the elements of List for which Goal fails.
+exclude(G, [], []).
+exclude(G, [X|Xs], Excluded) :-
+    (   call(G, X)
+    ->  Excluded = Rest
+    ;   Excluded = [X|Rest]
+    ),
+    exclude(G, Xs, Rest).
+
+%! forall(:Condition, :Action)
+%  Action succeeds for every solution of Condition.
+forall(Condition, Action) :- \+ (Condition, \+ Action).
@@ -0,0 +1,48 @@
+/* List predicates consulted before every program. Native list predicates
+   (length/2, msort/2, sort/2) live in the solver. */
+
+%! append(?L1, ?L2, ?L3)
+%  L3 is the concatenation of L1 and L2.
+append([], L, L).
+append([H|T], L, [H|R]) :- append(T, L, R).
+
+%! member(?X, ?List)
+%  X is an element of List; enumerates the elements on backtracking.
+member(X, [X|T]).
+member(X, [H|T]) :- member(X, T).
+
+%! memberchk(?X, +List)
+%  Like member/2, but succeeds at most once.
+memberchk(X, L) :- member(X, L), !.
+
+%! reverse(+List, -Reversed)
+reverse(L, R) :- reverse(L, [], R).
+reverse([], R, R).
+reverse([H|T], A, R) :- reverse(T, [H|A], R).
+
+%! nth0(?Index, ?List, ?Elem)
+%  Elem is the element of List at the 0-based Index.
+nth0(0, [E|T], E).
+nth0(I, [H|T], E) :- nth0(J, T, E), I is J + 1.
+
+%! nth1(?Index, ?List, ?Elem)
+%  Elem is the element of List at the 1-based Index.
+nth1(I, L, E) :- nth0(J, L, E), I is J + 1.
+
+%! last(?List, ?Last)
+last([X], X).
+last([H|T], X) :- last(T, X).
+
+%! select(?X, ?List, ?Rest)
+%  Rest is List with one occurrence of X removed.
+select(X, [X|T], T).
+select(X, [H|T], [H|R]) :- select(X, T, R).
+
+%! sum_list(+List, -Sum)
+sum_list(L, S) :- sum_list(L, 0, S).
+sum_list([], S, S).
+sum_list([X|T], A, S) :- B is A + X, sum_list(T, B, S).
+
+%! sumlist(+List, -Sum)
+%  Old name of sum_list/2.
+sumlist(L, S) :- sum_list(L, S).
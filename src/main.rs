@@ -2,8 +2,13 @@ pub mod parser;
 pub mod tokenizer;
 pub mod unificator;
 pub mod solver;
+pub mod arithmetic;
+pub mod library;
+pub mod error;
+pub mod operators;
+pub mod database;
 
-use std::{fs::File, io::{Read, Write}, panic, path::PathBuf, time::{Duration, Instant}};
+use std::time::{Duration, Instant};
 
 use crossterm::{
     event::{self, Event, KeyCode, KeyEvent, KeyModifiers},
@@ -19,9 +24,12 @@ use ratatui::{
 };
 
 use std::io;
-use crate::parser::{build_database, Parser};
-use crate::solver::{extract_query_results, get_query_vars};
-use crate::tokenizer::{tokenize, Statement, Token};
+use crate::parser::Parser;
+use crate::solver::Solutions;
+use crate::database::ClauseStore;
+use crate::tokenizer::Statement;
+use crate::operators::Operators;
+use crate::unificator::format_answer_with;
 
 #[derive(PartialEq)]
 enum Focus {
@@ -44,6 +52,8 @@ struct App {
     console_width: u16,
     top_height: u16,
     output_height: u16,
+    pending: Option<Solutions>, // query whose remaining answers have not been requested yet
+    consulted: Option<(String, ClauseStore)>, // the Editor text last loaded and its clauses, changed by assert and retract
 }
 
 impl App {
@@ -63,65 +73,133 @@ impl App {
             console_width: 50,
             top_height: 70,
             output_height: 30,
+            pending: None,
+            consulted: None,
         }
     }
 
-    fn evaluate_query(&self, query_str: &str) -> Vec<String> {
+    // Starts a new query and returns the output lines for its first answer.
+    // Remaining answers are kept in `self.pending` and requested with `next_answer`.
+    // The database is consulted again only when the Editor text has changed, so clauses added or removed by
+    // earlier queries stay until then. On loading, clauses with lexical or syntax errors are left out and reported as
+    // warnings before the answer, together with the singleton variables and the directives that failed.
+    fn evaluate_query(&mut self, query_str: &str) -> Vec<String> {
+        self.pending = None;
         let db_text = self.editor.join("\n");
-        let tokens = tokenize(&db_text);
-        match tokens {
-            Ok(tokens) => {
-                let mut parser = Parser::new(tokens);
-                let stmts = parser.parse_program();
+        let mut output = vec![];
+        let db = match &self.consulted {
+            Some((text, db)) if *text == db_text => db.clone(),
+            _ => {
+                let mut parser = Parser::from_program(&db_text);
+                let (stmts, diagnostics) = parser.parse_program();
+                output.extend(diagnostics.iter().map(|e| format!("Warning: {}", e)));
+                output.extend(parser.warnings().iter().map(|w| format!("Warning: {}", w)));
+                let (db, warnings) = solver::consult(library::with_library(stmts), Some(&db_text));
+                output.extend(warnings.iter().map(|w| format!("Warning: {}", w)));
+                self.consulted = Some((db_text, db.clone()));
+                db
+            }
+        };
 
-                let query = {
-                    let tokens = tokenize(query_str);
-                    match tokens {
-                        Ok(tokens) => {
-                            let mut parser = Parser::new(tokens);
-                            match parser.parse_statement() {
-                                Ok(Statement::Query { body }) => body,
-                                _ => return vec!["Expected a query!".to_string()],
-                            }
-                        },
-                        _ => return vec!["Tokenizer error".to_string()]
-                    }
-                };
+        let query = Parser::from_source(query_str).and_then(|mut query_parser| {
+            query_parser.set_operators(db.operators());
+            query_parser.set_double_quotes(db.double_quotes());
+            query_parser.parse_statement()
+        });
+        let answer = match query {
+            Ok(Statement::Query { body }) => {
+                self.pending = Some(solver::solve(&body, db));
+                self.next_answer()
+            }
+            Ok(_) => "Expected a query!".to_string(),
+            Err(e) => format!("Error in query: {}", e),
+        };
+        output.push(answer);
+        output
+    }
 
-                match stmts {
-                    Ok(stmts) => {
-                        let tree = solver::resolve_query(&query, &stmts);
-                        let query_vars = get_query_vars(&query);
-                        let results = extract_query_results(&tree, &query_vars);
+    // Computes the next answer of the pending query. The line ends with '.' once no alternatives are left,
+    // otherwise the query stays pending until the user asks for more or stops.
+    fn next_answer(&mut self) -> String {
+        let Some(solutions) = self.pending.as_mut() else {
+            return "false.".to_string();
+        };
+        match solutions.next() {
+            Some(Err(e)) => {
+                self.pending = None;
+                format!("Error: {}", e)
+            }
+            Some(Ok(subs)) => {
+                // the operators of the database, which the query itself may have changed with op/3
+                let operators = self.consulted.as_ref().map_or_else(Operators::default, |(_, db)| db.operators());
+                let answer = format_answer_with(&subs, &operators);
+                if solutions.is_exhausted() {
+                    self.pending = None;
+                    format!("{}.", answer)
+                } else {
+                    answer
+                }
+            }
+            None => {
+                self.pending = None;
+                "false.".to_string()
+            }
+        }
+    }
 
-                        if results.is_empty() {
-                            vec!["No solutions.".to_string()]
-                        } else {
-                            results
-                                .into_iter()
-                                .map(|subs| {
-                                    subs.into_iter()
-                                        .map(|(var, term)| format!("{} = {:?}", var, term))
-                                        .collect::<Vec<_>>()
-                                        .join(", ")
-                                })
-                                .collect()
-                        }
-                    }
-                    Err(e) => {
-                        eprintln!("Parse error: {}", e);
-                        vec!["Parse error".to_string()]
+    // Handles ';' / Space (next answer), Enter / '.' (stop) and 'a' (all remaining answers)
+    // while a query has answers pending.
+    fn answer_key(&mut self, code: KeyCode) {
+        match code {
+            KeyCode::Char(';') | KeyCode::Char(' ') => {
+                if let Some(last) = self.output.last_mut() {
+                    last.push_str(" ;");
+                }
+                let answer = self.next_answer();
+                self.output.push(answer);
+            }
+            KeyCode::Char('a') => {
+                while self.pending.is_some() {
+                    if let Some(last) = self.output.last_mut() {
+                        last.push_str(" ;");
                     }
+                    let answer = self.next_answer();
+                    self.output.push(answer);
                 }
-            },
-            Err(e) => {
-                eprintln!("Tokenize error: {}", e);
-                vec!["Tokenize error".to_string()]
             }
+            _ => self.stop_query(),
         }
+    }
 
+    // Stops asking for answers of the pending query, which ends its last answer
+    fn stop_query(&mut self) {
+        self.pending = None;
+        if let Some(last) = self.output.last_mut() {
+            last.push('.');
+        }
     }
 
+    // Grows the editor pane by `delta` percent at the expense of the console (or shrinks it if negative)
+    fn resize_editor(&mut self, delta: i16) {
+        if delta > 0 && self.editor_width < 80 {
+            self.editor_width += delta as u16;
+            self.console_width = self.console_width.saturating_sub(delta as u16);
+        } else if delta < 0 && self.console_width < 80 {
+            self.console_width += delta.unsigned_abs();
+            self.editor_width = self.editor_width.saturating_sub(delta.unsigned_abs());
+        }
+    }
+
+    // Grows the output pane by `delta` percent at the expense of the top panes (or shrinks it if negative)
+    fn resize_output(&mut self, delta: i16) {
+        if delta > 0 && self.top_height > 20 {
+            self.top_height -= delta as u16;
+            self.output_height += delta as u16;
+        } else if delta < 0 && self.output_height > 20 {
+            self.output_height -= delta.unsigned_abs();
+            self.top_height += delta.unsigned_abs();
+        }
+    }
 
     fn insert_char(&mut self, c: char) {
         if self.focus == Focus::Editor {
@@ -129,6 +207,10 @@ impl App {
             line.insert(self.cursor_x, c);
             self.cursor_x += 1;
         } else {
+            // typing a new query stops the pending one
+            if self.pending.is_some() {
+                self.stop_query();
+            }
             self.console_input.insert(self.console_cursor_x, c);
             self.console_cursor_x += 1;
         }
@@ -170,16 +252,16 @@ fn main() -> Result<(), io::Error> {
             let chunks = Layout::default()
                 .direction(Direction::Vertical)
                 .constraints([
-                    Constraint::Percentage(app.top_height.into()),
-                    Constraint::Percentage(app.output_height.into()),
+                    Constraint::Percentage(app.top_height),
+                    Constraint::Percentage(app.output_height),
                 ])
-                .split(f.size());
+                .split(f.area());
 
             let top_chunks = Layout::default()
                 .direction(Direction::Horizontal)
                 .constraints([
-                    Constraint::Percentage(app.editor_width.into()),
-                    Constraint::Percentage(app.console_width.into()),
+                    Constraint::Percentage(app.editor_width),
+                    Constraint::Percentage(app.console_width),
                 ])
                 .split(chunks[0]);
 
@@ -202,8 +284,13 @@ fn main() -> Result<(), io::Error> {
             } else {
                 Style::default().fg(Color::White)
             };
+            let console_title = if app.pending.is_some() {
+                "Console (; next, . stop, a all)"
+            } else {
+                "Console"
+            };
             let console_widget = Paragraph::new(app.console_input.as_str())
-                .block(Block::default().title("Console").borders(Borders::ALL).style(console_style))
+                .block(Block::default().title(console_title).borders(Borders::ALL).style(console_style))
                 .style(Style::default().fg(Color::Rgb(0, 100, 0)))
                 .scroll((app.console_scroll, 0))
                 .wrap(Wrap { trim: false });
@@ -227,7 +314,7 @@ fn main() -> Result<(), io::Error> {
                     .saturating_sub(app.editor_scroll)
                     .min(top_chunks[0].height.saturating_sub(2))
                     + 1;
-                f.set_cursor(x, y);
+                f.set_cursor_position((x, y));
             }
         })?;
 
@@ -260,6 +347,12 @@ fn main() -> Result<(), io::Error> {
                 KeyCode::Right if modifiers.contains(KeyModifiers::CONTROL) => {
                     app.focus = Focus::Console;
                 }
+                // Brackets are list syntax, so the output pane is resized with Ctrl+↑/↓ instead
+                KeyCode::Up if modifiers.contains(KeyModifiers::CONTROL) => app.resize_output(5),
+                KeyCode::Down if modifiers.contains(KeyModifiers::CONTROL) => app.resize_output(-5),
+                // the answer keys only act on a pending query while nothing has been typed in the Console
+                KeyCode::Char(';' | ' ' | '.' | 'a') | KeyCode::Enter
+                    if app.focus == Focus::Console && app.pending.is_some() && app.console_input.is_empty() => app.answer_key(code),
                 KeyCode::Up => match app.focus {
                     Focus::Editor => {
                         if app.cursor_y > 0 { app.cursor_y -= 1; }
@@ -286,28 +379,10 @@ fn main() -> Result<(), io::Error> {
                     }
                 },
                 KeyCode::Char('+') if modifiers.contains(KeyModifiers::CONTROL) => { // no working
-                    if app.editor_width < 80 {
-                        app.editor_width += 5;
-                        app.console_width = app.console_width.saturating_sub(5);
-                    }
+                    app.resize_editor(5);
                 }
                 KeyCode::Char('-') if modifiers.contains(KeyModifiers::CONTROL) => { // not working
-                    if app.console_width < 80 {
-                        app.console_width += 5;
-                        app.editor_width = app.editor_width.saturating_sub(5);
-                    }
-                }
-                KeyCode::Char('[') => {
-                    if app.top_height > 20 {
-                        app.top_height -= 5;
-                        app.output_height += 5;
-                    }
-                }
-                KeyCode::Char(']') => {
-                    if app.output_height > 20 {
-                        app.output_height -= 5;
-                        app.top_height += 5;
-                    }
+                    app.resize_editor(-5);
                 }
                 KeyCode::Char(c) => app.insert_char(c),
                 KeyCode::Backspace => app.backspace(),
@@ -329,26 +404,18 @@ F1            Quit\n\
 Ctrl+←/→     Switch focus\n\
 ↑/↓          Scroll active pane\n\
 + / -        Resize Editor vs Console\n\
-[ / ]        Resize Top vs Output\n\
+Ctrl+↑/↓     Resize Top vs Output\n\
 Enter        Newline (Editor) / Run (Console)\n\
+; / Space    Next answer of a query\n\
+Enter / .    Stop asking for answers\n\
+a            Show all remaining answers\n\
 --help       Show this help text"
                                         .to_string(),
                                 ),
                                 _ => { //app.output.push(format!("> {}", cmd)),
-                                    let result = std::panic::catch_unwind(|| {
-                                        app.evaluate_query(&cmd)
-                                    });
-
-                                    match result {
-                                        Ok(output_vec) => {
-                                            app.output.push(format!("> {}", cmd));
-                                            app.output.extend(output_vec);
-                                        }
-                                        Err(_) => {
-                                            app.output.push("Error: not a query or a command! (or database has errors)".to_string());
-
-                                        }
-                                    }
+                                    let output_vec = app.evaluate_query(&cmd);
+                                    app.output.push(format!("> {}", cmd));
+                                    app.output.extend(output_vec);
 
                                     app.console_input.clear();
                                     app.console_cursor_x = 0;
@@ -0,0 +1,214 @@
+// The operator table used to read and write terms, extensible at runtime with op/3
+use crate::error::PrologError;
+use crate::tokenizer::{Number, Term};
+use crate::unificator::format_term;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpType {
+    Xfx, // non-associative infix
+    Xfy, // right-associative infix
+    Yfx, // left-associative infix
+    Fy,  // prefix, the argument may be an operator term of the same priority
+    Fx,  // prefix
+    Xf,  // postfix
+    Yf,  // postfix, the argument may be an operator term of the same priority
+}
+
+impl OpType {
+    pub fn from_name(specifier: &str) -> Option<OpType> {
+        match specifier {
+            "xfx" => Some(OpType::Xfx),
+            "xfy" => Some(OpType::Xfy),
+            "yfx" => Some(OpType::Yfx),
+            "fy" => Some(OpType::Fy),
+            "fx" => Some(OpType::Fx),
+            "xf" => Some(OpType::Xf),
+            "yf" => Some(OpType::Yf),
+            _ => None,
+        }
+    }
+
+    // The highest priorities allowed for the left and right arguments of an operator of `priority`
+    pub fn argument_priorities(self, priority: u16) -> (u16, u16) {
+        let below = priority.saturating_sub(1);
+        match self {
+            OpType::Xfx => (below, below),
+            OpType::Xfy => (below, priority),
+            OpType::Yfx => (priority, below),
+            OpType::Fy => (0, priority),
+            OpType::Fx => (0, below),
+            OpType::Xf => (below, 0),
+            OpType::Yf => (priority, 0),
+        }
+    }
+}
+
+// Prefix, infix and postfix operators are kept apart, since a name such as - can be both prefix and infix
+#[derive(Debug, Clone)]
+pub struct Operators {
+    prefix: HashMap<String, (u16, OpType)>,
+    infix: HashMap<String, (u16, OpType)>,
+    postfix: HashMap<String, (u16, OpType)>,
+}
+
+// The standard operators of ISO Prolog, plus the declarations used in directives
+const STANDARD: &[(u16, &str, &[&str])] = &[
+    (1200, "xfx", &[":-", "-->"]),
+    (1200, "fx", &[":-", "?-"]),
+    (1150, "fx", &["dynamic", "discontiguous", "initialization", "multifile", "table"]),
+    (1100, "xfy", &[";", "|"]),
+    (1050, "xfy", &["->", "*->"]),
+    (1000, "xfy", &[","]),
+    (900, "fy", &["\\+"]),
+    (700, "xfx", &["=", "\\=", "==", "\\==", "@<", "@>", "@=<", "@>=", "=..", "is", "=:=", "=\\=", "<", ">", "=<", ">="]),
+    (600, "xfy", &[":"]),
+    (500, "yfx", &["+", "-", "/\\", "\\/", "xor"]),
+    (400, "yfx", &["*", "/", "//", "rem", "mod", "div", "rdiv", "<<", ">>"]),
+    (200, "xfx", &["**"]),
+    (200, "xfy", &["^"]),
+    (200, "fy", &["-", "+", "\\"]),
+];
+
+impl Default for Operators {
+    fn default() -> Self {
+        let mut operators = Operators { prefix: HashMap::new(), infix: HashMap::new(), postfix: HashMap::new() };
+        for &(priority, specifier, names) in STANDARD {
+            let op_type = OpType::from_name(specifier).expect("standard operator types are valid");
+            for name in names {
+                operators.add(priority, op_type, name);
+            }
+        }
+        operators
+    }
+}
+
+impl Operators {
+    // The standard operator table, built once
+    pub fn standard() -> &'static Operators {
+        static STANDARD_OPERATORS: OnceLock<Operators> = OnceLock::new();
+        STANDARD_OPERATORS.get_or_init(Operators::default)
+    }
+
+    pub fn prefix(&self, name: &str) -> Option<(u16, OpType)> {
+        self.prefix.get(name).copied()
+    }
+
+    pub fn infix(&self, name: &str) -> Option<(u16, OpType)> {
+        self.infix.get(name).copied()
+    }
+
+    pub fn postfix(&self, name: &str) -> Option<(u16, OpType)> {
+        self.postfix.get(name).copied()
+    }
+
+    // True if `name` is an operator of any kind
+    pub fn is_operator(&self, name: &str) -> bool {
+        self.prefix.contains_key(name) || self.infix.contains_key(name) || self.postfix.contains_key(name)
+    }
+
+    // Declares `name` as an operator, replacing an earlier declaration of the same kind; priority 0 removes it
+    pub fn add(&mut self, priority: u16, op_type: OpType, name: &str) {
+        let table = match op_type {
+            OpType::Fy | OpType::Fx => &mut self.prefix,
+            OpType::Xfx | OpType::Xfy | OpType::Yfx => &mut self.infix,
+            OpType::Xf | OpType::Yf => &mut self.postfix,
+        };
+        if priority == 0 {
+            table.remove(name);
+        } else {
+            table.insert(name.to_string(), (priority, op_type));
+        }
+    }
+
+    // op(Priority, Type, Names): checks the arguments like ISO op/3 and declares each of the names.
+    // The table only changes once all the arguments are valid.
+    pub fn op(&mut self, priority: &Term, specifier: &Term, names: &Term) -> Result<(), PrologError> {
+        let priority = match priority {
+            Term::Variable(_) => return Err(PrologError::Instantiation),
+            Term::Number(Number::Integer(p)) if (0..=1200).contains(p) => *p as u16,
+            Term::Number(Number::Integer(_) | Number::BigInt(_)) => {
+                return Err(PrologError::domain_error("operator_priority", format_term(priority)))
+            }
+            _ => return Err(PrologError::type_error("integer", format_term(priority))),
+        };
+        let op_type = match specifier {
+            Term::Variable(_) => return Err(PrologError::Instantiation),
+            Term::Constant(name) => OpType::from_name(name)
+                .ok_or_else(|| PrologError::domain_error("operator_specifier", format_term(specifier)))?,
+            _ => return Err(PrologError::type_error("atom", format_term(specifier))),
+        };
+        let names: Vec<&Term> = match names {
+            Term::Compound { name, args } if name == "." && args.len() == 2 => match names.list_items() {
+                (items, tail) if *tail == Term::nil() => items,
+                _ => return Err(PrologError::type_error("list", format_term(names))),
+            },
+            _ => vec![names],
+        };
+        let mut declared = vec![];
+        for name in names {
+            let name = match name {
+                Term::Variable(_) => return Err(PrologError::Instantiation),
+                Term::Constant(name) => name,
+                _ => return Err(PrologError::type_error("atom", format_term(name))),
+            };
+            // ',' is part of the syntax, and '|' may only be an infix operator above the priority of ','
+            let bar_allowed = matches!(op_type, OpType::Xfx | OpType::Xfy | OpType::Yfx) && (priority == 0 || priority > 1000);
+            if name == "," || (name == "|" && !bar_allowed) || name == "[]" || name == "{}" {
+                return Err(PrologError::permission_error("modify", "operator", format_term(&Term::Constant(name.clone()))));
+            }
+            declared.push(name);
+        }
+        declared.into_iter().for_each(|name| self.add(priority, op_type, name));
+        Ok(())
+    }
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_standard_operators() {
+        let operators = Operators::standard();
+        assert_eq!(operators.infix(":-"), Some((1200, OpType::Xfx)));
+        assert_eq!(operators.prefix(":-"), Some((1200, OpType::Fx)));
+        assert_eq!(operators.infix("-"), Some((500, OpType::Yfx)));
+        assert_eq!(operators.prefix("-"), Some((200, OpType::Fy)));
+        assert_eq!(operators.postfix("-"), None);
+        assert!(operators.is_operator("mod") && !operators.is_operator("foo"));
+        assert_eq!(OpType::Xfy.argument_priorities(1000), (999, 1000));
+        assert_eq!(OpType::Yfx.argument_priorities(500), (500, 499));
+    }
+
+    #[test]
+    fn test_op() {
+        let atom = |name: &str| Term::Constant(name.to_string());
+        let int = |n: i64| Term::Number(Number::Integer(n));
+        let mut operators = Operators::default();
+
+        operators.op(&int(700), &atom("xfx"), &Term::list(vec![atom("==>"), atom("likes")], Term::nil())).unwrap();
+        assert_eq!(operators.infix("==>"), Some((700, OpType::Xfx)));
+        assert_eq!(operators.infix("likes"), Some((700, OpType::Xfx)));
+        operators.op(&int(0), &atom("xfx"), &atom("likes")).unwrap();
+        assert_eq!(operators.infix("likes"), None);
+        operators.op(&int(100), &atom("xf"), &atom("!")).unwrap();
+        assert_eq!(operators.postfix("!"), Some((100, OpType::Xf)));
+
+        let error = |p: Term, t: Term, n: Term| Operators::default().op(&p, &t, &n).unwrap_err().to_string();
+        assert_eq!(error(int(1201), atom("xfx"), atom("a")), "Domain error: operator_priority expected, found 1201");
+        assert_eq!(error(int(700), atom("xyz"), atom("a")), "Domain error: operator_specifier expected, found xyz");
+        assert_eq!(error(int(700), atom("xfx"), int(1)), "Type error: atom expected, found 1");
+        assert_eq!(error(Term::Variable("P".to_string()), atom("xfx"), atom("a")), "Instantiation error");
+        assert_eq!(error(int(700), atom("xfx"), atom(",")), "Permission error: cannot modify operator ','");
+        assert_eq!(error(int(700), atom("fy"), atom("|")), "Permission error: cannot modify operator '|'");
+
+        // a bad name leaves the whole table as it was
+        let names = Term::list(vec![atom("==>"), atom("meets"), atom(",")], Term::nil());
+        assert!(operators.op(&int(800), &atom("xfx"), &names).is_err());
+        assert_eq!(operators.infix("==>"), Some((700, OpType::Xfx)));
+        assert_eq!(operators.infix("meets"), None);
+    }
+}
@@ -1,23 +1,117 @@
 // Recursive Descent Parser (Top-Down)
-use crate::tokenizer::{Token, Statement, Atom, Term, Database, Rule};
+use crate::error::{PrologError, SourceError};
+use crate::operators::Operators;
+use crate::unificator::format_term;
+use crate::tokenizer::{tokenize_program, tokenize_with_comments, Comment, Atom, Database, Number, Rule, Span, SpannedToken, Statement, Term, TermSpan, Token};
 
 pub struct Parser {
-    tokens: Vec<Token>,
+    tokens: Vec<SpannedToken>,
     pos: usize,
+    source: Option<String>, // the text the tokens were read from, for error excerpts
+    comments: Vec<Comment>, // documentation comments not yet attached to a clause
+    docs: Vec<DocComment>,
+    warnings: Vec<SourceError>,
+    lexical_errors: Vec<PrologError>, // the errors of the clauses the tokenizer left out
+    double_quotes: DoubleQuotes,
+    operators: Operators, // the operators known so far, extended by op/3 directives
+}
+
+// What a double-quoted string such as "abc" reads as, like the ISO double_quotes flag
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DoubleQuotes {
+    #[default]
+    Codes,  // a list of character codes, [97,98,99]
+    Chars,  // a list of one-char atoms, [a,b,c]
+    Atom,   // the atom abc
+    String, // a string object, "abc"
+}
+
+impl DoubleQuotes {
+    // The value of the flag with the given name, as in set_prolog_flag(double_quotes, chars)
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "codes" => Some(DoubleQuotes::Codes),
+            "chars" => Some(DoubleQuotes::Chars),
+            "atom" => Some(DoubleQuotes::Atom),
+            "string" => Some(DoubleQuotes::String),
+            _ => None,
+        }
+    }
+}
+
+// The documentation comments written right before a clause, and the predicate of that clause
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DocComment {
+    pub predicate: String, // name/arity
+    pub text: String,
 }
 
 impl Parser {
-    pub fn new(tokens: Vec<Token>) -> Self {
-        Self { tokens, pos: 0 }
+    pub fn new(tokens: Vec<SpannedToken>) -> Self {
+        Self {
+            tokens,
+            pos: 0,
+            source: None,
+            comments: vec![],
+            docs: vec![],
+            warnings: vec![],
+            lexical_errors: vec![],
+            double_quotes: DoubleQuotes::default(),
+            operators: Operators::default(),
+        }
+    }
+
+    // The operator table, including the operators declared by the program read so far
+    pub fn operators(&self) -> &Operators {
+        &self.operators
+    }
+
+    // Reads the following terms with `operators`, e.g. a query with the operators of its program
+    pub fn set_operators(&mut self, operators: Operators) {
+        self.operators = operators;
+    }
+
+    // Sets how double-quoted strings are read from now on
+    pub fn set_double_quotes(&mut self, double_quotes: DoubleQuotes) {
+        self.double_quotes = double_quotes;
+    }
+
+    // How double-quoted strings are read, including a change by a set_prolog_flag/2 directive read so far
+    pub fn double_quotes(&self) -> DoubleQuotes {
+        self.double_quotes
+    }
+
+    // Tokenizes `source` and keeps it, so that parse errors can quote the offending line
+    // and documentation comments can be attached to the clauses they precede
+    pub fn from_source(source: &str) -> Result<Self, PrologError> {
+        let (tokens, comments) = tokenize_with_comments(source)?;
+        Ok(Self { source: Some(source.to_string()), comments, ..Self::new(tokens) })
+    }
+
+    // A parser for a whole program that skips the clauses with lexical errors; `parse_program` reports
+    // those errors together with the syntax errors
+    pub fn from_program(source: &str) -> Self {
+        let (tokens, comments, lexical_errors) = tokenize_program(source);
+        Self { source: Some(source.to_string()), comments, lexical_errors, ..Self::new(tokens) }
+    }
+
+    // The documentation comments found by `parse_program`
+    pub fn doc_comments(&self) -> &[DocComment] {
+        &self.docs
+    }
+
+    // The warnings found by `parse_program`, e.g. singleton variables
+    pub fn warnings(&self) -> &[SourceError] {
+        &self.warnings
     }
 
     fn peek(&self) -> Option<&Token> {
-        self.tokens.get(self.pos)
+        self.tokens.get(self.pos).map(|t| &t.token)
     }
 
     fn consume(&mut self) -> Option<Token> {
         if self.pos < self.tokens.len() {
-            let tok = self.tokens[self.pos].clone();
+            let tok = self.tokens[self.pos].token.clone();
             self.pos += 1;
             Some(tok)
         } else {
@@ -25,30 +119,76 @@ impl Parser {
         }
     }
 
-    fn expect(&mut self, expected: &Token) -> Result<(), String> {
+    // The span of the next token, or an empty span just after the last one at the end of the input
+    fn current_span(&self) -> Span {
+        match (self.tokens.get(self.pos), self.tokens.last()) {
+            (Some(token), _) => token.span,
+            (None, Some(last)) => {
+                let width = last.span.end - last.span.start;
+                Span { start: last.span.end, end: last.span.end, line: last.span.line, column: last.span.column + width }
+            }
+            (None, None) => Span { line: 1, column: 1, ..Span::default() },
+        }
+    }
+
+    // The span of the tokens consumed since position `start`
+    fn span_from(&self, start: usize) -> Span {
+        match self.pos.checked_sub(1).filter(|&last| last >= start) {
+            Some(last) => self.tokens[start].span.to(self.tokens[last].span),
+            None => self.current_span(),
+        }
+    }
+
+    fn error_at(&self, span: Span, message: &str) -> PrologError {
+        PrologError::Syntax(SourceError::new(message, span, self.source.as_deref()))
+    }
+
+    // An error about the next token, e.g. "Expected ')', got '.'"
+    fn unexpected(&self, expected: &str) -> PrologError {
+        let found = match self.peek() {
+            Some(token) => format!("'{}'", token),
+            None => "end of input".to_string(),
+        };
+        self.error_at(self.current_span(), &format!("Expected {}, got {}", expected, found))
+    }
+
+    fn expect(&mut self, expected: &Token) -> Result<(), PrologError> {
         match self.peek() {
             Some(tok) if tok == expected => {
                 self.consume();
                 Ok(())
             }
-            other => Err(format!("Expected {:?}, got {:?}", expected, other)),
+            _ => Err(self.unexpected(&format!("'{}'", expected))),
         }
     }
 
-    pub fn parse_statement(&mut self) -> Result<Statement, String> {
+    pub fn parse_statement(&mut self) -> Result<Statement, PrologError> {
         match self.peek() {
             Some(Token::QueryOperator) => {
                 self.consume(); // consume "?-"
-                let mut body = vec![self.parse_atom()?];
-                while let Some(Token::Comma) = self.peek() {
-                    self.consume();
-                    body.push(self.parse_atom()?);
-                }
+                let body = self.parse_body()?;
                 self.expect(&Token::Period)?;
                 Ok(Statement::Query { body })
             }
+            Some(Token::RuleArrow) => {
+                self.consume(); // consume ":-"
+                let body = self.parse_body()?;
+                self.expect(&Token::Period)?;
+                Ok(Statement::Directive { body })
+            }
             _ => {
-                let head = self.parse_atom()?;
+                let start = self.pos;
+                let (head, spans) = self.parse_expression(1199)?;
+                let span = self.span_from(start);
+                let Some(head) = Atom::from_term(&head) else {
+                    return Err(self.error_at(span, "Expected atom"));
+                };
+                let head = head.with_spans(&TermSpan::new(span, spans.args));
+                if is_control_construct(&head.name, head.args.len()) {
+                    let indicator = format!("{}/{}", format_term(&Term::Constant(head.name.clone())), head.args.len());
+                    let error = PrologError::permission_error("modify", "static_procedure", indicator);
+                    return Err(self.error_at(span, &error.to_string()));
+                }
                 match self.peek() {
                     Some(Token::Period) => {
                         self.consume();
@@ -56,81 +196,379 @@ impl Parser {
                     }
                     Some(Token::RuleArrow) => {
                         self.consume();
-                        let mut body = vec![self.parse_atom()?];
-                        while let Some(Token::Comma) = self.peek() {
-                            self.consume();
-                            body.push(self.parse_atom()?);
-                        }
+                        let body = self.parse_body()?;
                         self.expect(&Token::Period)?;
                         Ok(Statement::Rule { head, body })
                     }
-                    other => Err(format!("Expected '.' or ':-' after atom, got {:?}", other)),
+                    _ => Err(self.unexpected("'.' or ':-' after atom")),
                 }
             }
         }
     }
 
-    fn parse_atom(&mut self) -> Result<Atom, String> {
-        if let Some(Token::Identifier(name)) = self.peek() {
-            let name = name.clone();
-            self.consume();
-            let args = if let Some(Token::LParen) = self.peek() {
+    // Parses a clause body or a query and splits it into its top-level conjuncts, each with its own span.
+    // An operator above ',' such as ';' or '->' takes the whole conjunction before it as its left operand,
+    // so control constructs nest as goal trees: ','/2, ';'/2 and '->'/2 terms whose arguments are goals.
+    fn parse_body(&mut self) -> Result<Vec<Atom>, PrologError> {
+        let start = self.pos;
+        let mut goals = vec![];
+        let mut priority; // of the last goal
+        loop {
+            let goal_start = self.pos;
+            // only the first goal may be a prefix operator above ',', as in ":- dynamic a/1, b/2.", and then
+            // its argument takes the rest of the body
+            let (operand, operand_spans, operand_priority) = self.parse_primary(if goals.is_empty() { 1199 } else { 999 })?;
+            let max_priority = if operand_priority > 999 { 1199 } else { 999 };
+            let (goal, spans, goal_priority) = self.parse_infix(operand, operand_spans, operand_priority, max_priority)?;
+            priority = goal_priority;
+            goals.push((goal, TermSpan::new(self.span_from(goal_start), spans.args)));
+            if let Some(Token::Comma) = self.peek() {
                 self.consume();
-                let mut args = vec![self.parse_term()?];
-                while let Some(Token::Comma) = self.peek() {
-                    self.consume();
-                    args.push(self.parse_term()?);
-                }
-                self.expect(&Token::RParen)?;
-                args
             } else {
-                vec![]
-            };
-            Ok(Atom { name, args })
-        } else {
-            Err(format!("Expected identifier for atom, got {:?}", self.peek()))
+                break;
+            }
         }
+
+        let next_infix = self.peek().and_then(operator_name).and_then(|name| self.operators.infix(&name));
+        if next_infix.is_some_and(|(next_priority, _)| (1000..1200).contains(&next_priority)) {
+            let left_priority = if goals.len() == 1 { priority } else { 1000 };
+            let (left, left_spans) = conjunction(goals);
+            let (goal, spans, _) = self.parse_infix(left, left_spans, left_priority, 1199)?;
+            goals = vec![(goal, TermSpan::new(self.span_from(start), spans.args))];
+        }
+
+        goals
+            .into_iter()
+            .map(|(goal, spans)| match (&goal, Atom::from_term(&goal)) {
+                // a variable goal X is called as call(X)
+                (Term::Variable(_), _) => Ok(Atom::new("call", vec![goal]).with_spans(&TermSpan::new(spans.span, vec![spans.clone()]))),
+                (_, Some(atom)) => Ok(atom.with_spans(&spans)),
+                (_, None) => Err(self.error_at(spans.span, "Expected goal")),
+            })
+            .collect()
     }
 
-    fn parse_term(&mut self) -> Result<Term, String> {
-        match self.peek() {
-            Some(Token::Identifier(_)) => {
-                let atom = self.parse_atom()?;
-                if atom.args.is_empty() {
-                    Ok(Term::Constant(atom.name))
+    // Parses "(term, ..., term)" after a functor name, or nothing if no parenthesis follows
+    fn parse_arguments(&mut self) -> Result<(Vec<Term>, Vec<TermSpan>), PrologError> {
+        let (mut args, mut spans) = (vec![], vec![]);
+        if let Some(Token::LParen) = self.peek() {
+            self.consume();
+            loop {
+                let (arg, span) = self.parse_term()?;
+                args.push(arg);
+                spans.push(span);
+                if let Some(Token::Comma) = self.peek() {
+                    self.consume();
                 } else {
-                    Ok(Term::Compound {
-                        name: atom.name,
-                        args: atom.args,
-                    })
+                    break;
                 }
             }
+            self.expect(&Token::RParen)?;
+        }
+        Ok((args, spans))
+    }
+
+    // Parses an argument of a compound term or an element of a list, which is below the priority of ','
+    fn parse_term(&mut self) -> Result<(Term, TermSpan), PrologError> {
+        self.parse_expression(999)
+    }
+
+    // Operator precedence parsing: parses a term whose priority is at most `max_priority`.
+    // Returns the term together with where it and its arguments were read.
+    fn parse_expression(&mut self, max_priority: u16) -> Result<(Term, TermSpan), PrologError> {
+        let (left, left_spans, left_priority) = self.parse_primary(max_priority)?;
+        let (term, spans, _) = self.parse_infix(left, left_spans, left_priority, max_priority)?;
+        Ok((term, spans))
+    }
+
+    // Extends the operand `left` with the infix and postfix operators that follow it, as long as the
+    // operator table allows them within `max_priority`. Returns the term together with its spans and priority.
+    fn parse_infix(&mut self, mut left: Term, mut left_spans: TermSpan, mut left_priority: u16, max_priority: u16) -> Result<(Term, TermSpan, u16), PrologError> {
+        while let Some(name) = self.peek().and_then(operator_name) {
+            if let Some((priority, op_type)) = self.operators.infix(&name) {
+                let (left_max, right_max) = op_type.argument_priorities(priority);
+                if priority <= max_priority && left_priority <= left_max {
+                    self.consume();
+                    let (right, right_spans) = self.parse_expression(right_max)?;
+                    // a | b between goals is the disjunction a ; b
+                    let name = if name == "|" { ";".to_string() } else { name };
+                    left = Term::Compound { name, args: vec![left, right] };
+                    left_spans = TermSpan::new(left_spans.span.to(right_spans.span), vec![left_spans, right_spans]);
+                    left_priority = priority;
+                    continue;
+                }
+            }
+            if let Some((priority, op_type)) = self.operators.postfix(&name) {
+                if priority <= max_priority && left_priority <= op_type.argument_priorities(priority).0 {
+                    self.consume();
+                    left = Term::Compound { name, args: vec![left] };
+                    left_spans = TermSpan::new(left_spans.span.to(self.tokens[self.pos - 1].span), vec![left_spans]);
+                    left_priority = priority;
+                    continue;
+                }
+            }
+            break;
+        }
+        Ok((left, left_spans, left_priority))
+    }
+
+    // Parses an operand: a number, variable, string, atom, compound term, parenthesised term, list
+    // or prefix operator term. Returns the term together with its spans and priority.
+    fn parse_primary(&mut self, max_priority: u16) -> Result<(Term, TermSpan, u16), PrologError> {
+        let start = self.pos;
+        let leaf = |parser: &Parser, term: Term| Ok((term, TermSpan::new(parser.span_from(start), vec![]), 0));
+        match self.peek().cloned() {
+            Some(Token::Number(n)) => {
+                self.consume();
+                leaf(self, Term::Number(n))
+            }
             Some(Token::Variable(name)) => {
-                let name = name.clone();
                 self.consume();
-                Ok(Term::Variable(name))
+                leaf(self, Term::Variable(name))
+            }
+            Some(Token::String(text)) => {
+                self.consume();
+                let term = match self.double_quotes {
+                    DoubleQuotes::Codes => Term::list(text.chars().map(|c| Term::Number(Number::Integer(c as i64))).collect(), Term::nil()),
+                    DoubleQuotes::Chars => Term::list(text.chars().map(|c| Term::Constant(c.to_string())).collect(), Term::nil()),
+                    DoubleQuotes::Atom => Term::Constant(text),
+                    DoubleQuotes::String => Term::String(text),
+                };
+                leaf(self, term)
+            }
+            Some(Token::Cut) => {
+                self.consume();
+                leaf(self, Term::Constant("!".to_string()))
+            }
+            Some(Token::LParen) => {
+                self.consume();
+                let (term, spans) = self.parse_expression(1200)?;
+                self.expect(&Token::RParen)?;
+                Ok((term, TermSpan::new(self.span_from(start), spans.args), 0))
+            }
+            Some(Token::LBracket) => {
+                self.consume();
+                let (list, spans) = self.parse_list(start)?;
+                Ok((list, spans, 0))
             }
-            other => Err(format!("Expected term, got {:?}", other)),
+            Some(token) => match atom_name(&token) {
+                Some(name) => {
+                    self.consume();
+                    self.parse_name(name, start, max_priority)
+                }
+                None => Err(self.unexpected("term")),
+            },
+            None => Err(self.unexpected("term")),
+        }
+    }
+
+    // Parses what follows a name read from position `start`: its arguments in functional notation, the digits
+    // of a negative number, or the argument of a prefix operator. Otherwise the name is an atom.
+    fn parse_name(&mut self, name: String, start: usize, max_priority: u16) -> Result<(Term, TermSpan, u16), PrologError> {
+        // functional notation and negative numbers need the '(' or the digits right after the name
+        let adjacent = match (self.tokens.get(self.pos), self.pos.checked_sub(1).map(|last| &self.tokens[last])) {
+            (Some(next), Some(last)) => next.span.start == last.span.end,
+            _ => false,
+        };
+        match self.peek() {
+            Some(Token::LParen) if adjacent => {
+                let (args, spans) = self.parse_arguments()?;
+                Ok((Term::Compound { name, args }, TermSpan::new(self.span_from(start), spans), 0))
+            }
+            Some(Token::Number(n)) if name == "-" && adjacent => {
+                let n = -n.clone();
+                self.consume();
+                Ok((Term::Number(n), TermSpan::new(self.span_from(start), vec![]), 0))
+            }
+            // a prefix operator whose priority is too high for the context is just an atom there
+            Some(next) if self.starts_operand(next) => match self.operators.prefix(&name) {
+                Some((priority, op_type)) if priority <= max_priority => {
+                    let (arg, arg_spans) = self.parse_expression(op_type.argument_priorities(priority).1)?;
+                    let spans = TermSpan::new(self.span_from(start), vec![arg_spans]);
+                    Ok((Term::Compound { name, args: vec![arg] }, spans, priority))
+                }
+                _ => Ok((Term::Constant(name), TermSpan::new(self.span_from(start), vec![]), 0)),
+            },
+            _ => Ok((Term::Constant(name), TermSpan::new(self.span_from(start), vec![]), 0)),
+        }
+    }
+
+    // True if `token` can start the argument of a prefix operator. An infix operator cannot,
+    // so in "- = x" the - is an atom, unless it may also be a prefix operator as in "- - x".
+    fn starts_operand(&self, token: &Token) -> bool {
+        match atom_name(token) {
+            Some(name) => self.operators.infix(&name).is_none() || self.operators.prefix(&name).is_some(),
+            None => matches!(token, Token::Variable(_) | Token::Number(_) | Token::String(_) | Token::LParen | Token::LBracket | Token::Cut),
         }
     }
 
-    pub fn parse_program(&mut self) -> Result<Vec<Statement>, String> {
+    // Parses the rest of a list after the '[' at position `start`: either "]" or "term, ..., term [| tail]]".
+    // Each '.'/2 cell of the list spans from its element to the closing bracket.
+    fn parse_list(&mut self, start: usize) -> Result<(Term, TermSpan), PrologError> {
+        if let Some(Token::RBracket) = self.peek() {
+            self.consume();
+            return Ok((Term::nil(), TermSpan::new(self.span_from(start), vec![])));
+        }
+        let mut items = vec![self.parse_term()?];
+        while let Some(Token::Comma) = self.peek() {
+            self.consume();
+            items.push(self.parse_term()?);
+        }
+        let tail = match self.peek() {
+            Some(Token::Bar) => {
+                self.consume();
+                Some(self.parse_term()?)
+            }
+            _ => None,
+        };
+        self.expect(&Token::RBracket)?;
+        let end = self.tokens[self.pos - 1].span;
+        let (tail, tail_spans) = tail.unwrap_or_else(|| (Term::nil(), TermSpan::new(end, vec![])));
+        let spans = items.iter().rev().fold(tail_spans, |rest, (_, item)| TermSpan::new(item.span.to(end), vec![item.clone(), rest]));
+        let list = Term::list(items.into_iter().map(|(item, _)| item).collect(), tail);
+        Ok((list, TermSpan { span: self.span_from(start), ..spans }))
+    }
+
+    // Parses every statement of the input. A statement with a syntax error is skipped up to its
+    // closing '.', so one bad clause does not hide the rest; the errors are returned alongside,
+    // in source order together with the lexical errors of the clauses the tokenizer skipped.
+    pub fn parse_program(&mut self) -> (Vec<Statement>, Vec<PrologError>) {
         let mut stmts = Vec::new();
+        // each error with the position of its statement, to report them in source order
+        let mut errors: Vec<(usize, PrologError)> = std::mem::take(&mut self.lexical_errors)
+            .into_iter()
+            .map(|e| (e.span().map_or(0, |span| span.start), e))
+            .collect();
+        let mut comments = std::mem::take(&mut self.comments).into_iter().peekable();
 
         while self.peek().is_some() {
+            let start = self.current_span().start;
+            let mut doc = vec![];
+            while let Some(comment) = comments.next_if(|comment| comment.span.start < start) {
+                doc.push(comment.text);
+            }
             match self.parse_statement() {
-                Ok(stmt) => stmts.push(stmt),
-                Err(e) => return Err(e),
+                Ok(stmt) => {
+                    if let Statement::Directive { body } = &stmt {
+                        body.iter().for_each(|goal| self.read_directive(goal));
+                    }
+                    self.check_singletons(&stmt);
+                    if let (Statement::Fact(head) | Statement::Rule { head, .. }, false) = (&stmt, doc.is_empty()) {
+                        let predicate = format!("{}/{}", head.name, head.args.len());
+                        self.docs.push(DocComment { predicate, text: doc.join("\n") });
+                    }
+                    stmts.push(stmt)
+                }
+                Err(e) => {
+                    errors.push((start, e));
+                    self.skip_statement();
+                }
+            }
+        }
+
+        errors.sort_by_key(|&(start, _)| start);
+        (stmts, errors.into_iter().map(|(_, e)| e).collect())
+    }
+
+    // Applies the part of a directive that changes how the rest of the program is read: op/3 takes
+    // effect for the clauses that follow it. A malformed op/3 leaves the table unchanged; errors are
+    // reported when the directive itself is run, with the same checks.
+    fn read_directive(&mut self, goal: &Atom) {
+        match (goal.name.as_str(), goal.args.as_slice()) {
+            ("op", [priority, specifier, names]) => {
+                // op/3 checks all its arguments before it changes anything
+                let _ = self.operators.op(priority, specifier, names);
             }
+            ("set_prolog_flag", [Term::Constant(flag), Term::Constant(value)]) if flag == "double_quotes" => {
+                if let Some(double_quotes) = DoubleQuotes::from_name(value) {
+                    self.double_quotes = double_quotes;
+                }
+            }
+            _ => {}
         }
+    }
 
-        Ok(stmts)
+    // Warns about the named variables that occur only once in a clause, which are often typos.
+    // Variables starting with '_' are meant to be used once and are not reported.
+    fn check_singletons(&mut self, stmt: &Statement) {
+        let atoms: Vec<&Atom> = match stmt {
+            Statement::Fact(head) => vec![head],
+            Statement::Rule { head, body } => std::iter::once(head).chain(body).collect(),
+            Statement::Query { .. } | Statement::Directive { .. } => return,
+        };
+        let mut counts: Vec<(&str, usize)> = vec![];
+        for atom in atoms {
+            atom.args.iter().for_each(|arg| count_variables(arg, &mut counts));
+        }
+        let singletons: Vec<&str> = counts
+            .into_iter()
+            .filter(|&(name, count)| count == 1 && !name.starts_with('_'))
+            .map(|(name, _)| name)
+            .collect();
+        if !singletons.is_empty() {
+            let message = format!("Singleton variables: [{}]", singletons.join(","));
+            self.warnings.push(SourceError::new(message, stmt.span(), self.source.as_deref()));
+        }
+    }
+
+    // Skips the rest of a malformed statement, up to and including the next '.'
+    fn skip_statement(&mut self) {
+        while let Some(token) = self.consume() {
+            if token == Token::Period {
+                break;
+            }
+        }
     }
 }
 
 
-fn parse_tokens(tokens: Vec<Token>) -> Result<Statement, String> {
-    let mut parser = Parser::new(tokens);
+// The control constructs are not procedures, so no clause can define them
+fn is_control_construct(name: &str, arity: usize) -> bool {
+    matches!((name, arity), ("," | ";" | "->", 2) | ("!", 0))
+}
+
+// Counts the occurrences of each variable of `term`, in the order they first appear
+fn count_variables<'a>(term: &'a Term, counts: &mut Vec<(&'a str, usize)>) {
+    match term {
+        Term::Variable(name) => match counts.iter_mut().find(|(seen, _)| seen == name) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((name, 1)),
+        },
+        Term::Compound { args, .. } => args.iter().for_each(|arg| count_variables(arg, counts)),
+        _ => {}
+    }
+}
+
+// Joins goals into a single right-nested ','/2 term
+fn conjunction(goals: Vec<(Term, TermSpan)>) -> (Term, TermSpan) {
+    let mut goals = goals.into_iter().rev();
+    let last = goals.next().expect("a conjunction has at least one goal");
+    goals.fold(last, |(right, right_spans), (left, left_spans)| {
+        let spans = TermSpan::new(left_spans.span.to(right_spans.span), vec![left_spans, right_spans]);
+        (Term::Compound { name: ",".to_string(), args: vec![left, right] }, spans)
+    })
+}
+
+// The atom a token stands for when it is used as a name, e.g. ':-' for the rule arrow
+fn atom_name(token: &Token) -> Option<String> {
+    match token {
+        Token::Identifier(name) | Token::Operator(name) => Some(name.clone()),
+        Token::RuleArrow | Token::QueryOperator | Token::NotOperator | Token::IfArrow | Token::Semicolon => Some(token.to_string()),
+        _ => None,
+    }
+}
+
+// The name of the operator a token could stand for; ',' and '|' are operators but not atoms
+fn operator_name(token: &Token) -> Option<String> {
+    match token {
+        Token::Comma | Token::Bar => Some(token.to_string()),
+        _ => atom_name(token),
+    }
+}
+
+#[cfg(test)]
+fn parse_tokens(tokens: Vec<Token>) -> Result<Statement, PrologError> {
+    let mut parser = Parser::new(tokens.into_iter().map(|token| SpannedToken { token, span: Span::default() }).collect());
     parser.parse_statement()
 }
 
@@ -142,7 +580,7 @@ pub fn build_database(stmts: Vec<Statement>) -> Database {
         match stmt {
             Statement::Fact(atom) => facts.push(atom),
             Statement::Rule { head, body } => rules.push(Rule { head, body }),
-            Statement::Query { .. } => {
+            Statement::Query { .. } | Statement::Directive { .. } => {
                 // ignore here; queries will be parsed separately from console
             }
         }
@@ -158,11 +596,11 @@ pub fn build_database(stmts: Vec<Statement>) -> Database {
         _ => panic!("Expected query"),
     }
 }*/
-pub fn parse_query(tokens: Vec<Token>) -> Result<Vec<Atom>, String> {
+pub fn parse_query(tokens: Vec<SpannedToken>) -> Result<Vec<Atom>, PrologError> {
     let mut parser = Parser::new(tokens);
-    match parser.parse_statement() {
-        Ok(Statement::Query { body }) => Ok(body),
-        _ => Err("Expected query".to_string()),
+    match parser.parse_statement()? {
+        Statement::Query { body } => Ok(body),
+        statement => Err(parser.error_at(statement.span(), "Expected query")),
     }
 }
 
@@ -170,7 +608,7 @@ pub fn parse_query(tokens: Vec<Token>) -> Result<Vec<Atom>, String> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::tokenizer::{tokenize};
+    use crate::tokenizer::tokenize;
     use crate::tokenizer::Term::{Compound, Constant, Variable};
 
     #[test]
@@ -178,8 +616,8 @@ mod tests {
         let input = "?- ancestor(father(john), X), parent(X, mary).";
         let tokens = tokenize(input);
         match tokens {
-            Ok(T) => {
-                let mut parser = Parser::new(T);
+            Ok(tokens) => {
+                let mut parser = Parser::new(tokens);
                 let stmt = parser.parse_statement();
 
                 match stmt {
@@ -187,9 +625,9 @@ mod tests {
                         //println!("{:#?}", stmt);
                         let st = Statement::Query {
                             body: vec![
-                                Atom {
-                                    name: "ancestor".to_string(),
-                                    args: vec![
+                                Atom::new(
+                                    "ancestor",
+                                    vec![
                                         Compound {
                                             name: String::from("father"),
                                             args: vec![
@@ -197,15 +635,15 @@ mod tests {
                                             ]
                                         },
                                         Variable(String::from("X")),
-                                    ]
-                                },
-                                Atom {
-                                    name: "parent".to_string(),
-                                    args: vec![
+                                    ],
+                                ),
+                                Atom::new(
+                                    "parent",
+                                    vec![
                                         Variable(String::from("X")),
                                         Constant(String::from("mary")),
-                                    ]
-                                },
+                                    ],
+                                ),
                             ]
                         };
                         //println!("{:#?}", st);
@@ -243,16 +681,16 @@ mod tests {
                 //println!("{:#?}", stmt);
                 assert_eq!(
                     stmt,
-                    Statement::Fact(Atom {
-                        name: "parent".to_string(),
-                        args: vec![
+                    Statement::Fact(Atom::new(
+                        "parent",
+                        vec![
                             Constant("john".to_string()),
                             Constant("mary".to_string())
-                        ]
-                    })
+                        ],
+                    ))
                 );
             },
-            Err(err) => {}
+            Err(_err) => {}
         }
     }
 
@@ -321,7 +759,7 @@ mod tests {
         ];
         let stmt = parse_tokens(tokens);
         if let Ok(Statement::Query { body }) = stmt {
-            if let Term::Compound { name, args } = &body[0].args[0] {
+            if let Term::Compound { name, .. } = &body[0].args[0] {
                 assert_eq!(name, "father");
             } else {
                 panic!("Nested term parsing failed");
@@ -331,4 +769,318 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_rule_with_cut() {
+        let tokens = tokenize("max(X, Y, X) :- geq(X, Y), !.").unwrap();
+        let stmt = Parser::new(tokens).parse_statement();
+        if let Ok(Statement::Rule { body, .. }) = stmt {
+            assert_eq!(body.len(), 2);
+            assert_eq!(body[1], Atom::new("!", vec![]));
+        } else {
+            panic!("Rule parsing failed");
+        }
+    }
+
+    #[test]
+    fn test_parse_negation() {
+        let tokens = tokenize("?- \\+ \\+ p(X), not(q(X)).").unwrap();
+        let body = parse_query(tokens).unwrap();
+        let p = Compound { name: "p".to_string(), args: vec![Variable("X".to_string())] };
+        assert_eq!(body[0], Atom::new("\\+", vec![Compound { name: "\\+".to_string(), args: vec![p] }]));
+        assert_eq!(body[1].name, "not");
+    }
+
+    #[test]
+    fn test_parse_control_constructs() {
+        let tokens = tokenize("p(X) :- q(X), ( r(X) -> s ; t, u ; v ).").unwrap();
+        let stmt = Parser::new(tokens).parse_statement();
+        let Ok(Statement::Rule { body, .. }) = stmt else { panic!("Rule parsing failed") };
+        assert_eq!(body.len(), 2);
+
+        let goal = |name: &str| Term::Constant(name.to_string());
+        let pair = |name: &str, left: Term, right: Term| Compound { name: name.to_string(), args: vec![left, right] };
+        let r = Compound { name: "r".to_string(), args: vec![Variable("X".to_string())] };
+        let expected = pair(";",
+            pair("->", r, goal("s")),
+            pair(";", pair(",", goal("t"), goal("u")), goal("v")),
+        );
+        assert_eq!(Term::from(body[1].clone()), expected);
+    }
+
+    #[test]
+    fn test_parse_parenthesised_conjunction() {
+        let tokens = tokenize("?- (a, b), \\+ (c ; d).").unwrap();
+        let body = parse_query(tokens).unwrap();
+        let names: Vec<&str> = body.iter().map(|atom| atom.name.as_str()).collect();
+        // a parenthesised conjunction stays a single ','/2 goal
+        assert_eq!(names, vec![",", "\\+"]);
+        assert_eq!(body[1].args[0], Compound {
+            name: ";".to_string(),
+            args: vec![Constant("c".to_string()), Constant("d".to_string())],
+        });
+    }
+
+    #[test]
+    fn test_parse_operators() {
+        let tokens = tokenize("?- X is -1 + 2 * 3 - Y ^ 2 ^ 3, X =< 10 mod 4.").unwrap();
+        let body = parse_query(tokens).unwrap();
+        let op = |name: &str, left: Term, right: Term| Compound { name: name.to_string(), args: vec![left, right] };
+        let int = |n: i64| Term::Number(Number::Integer(n));
+        let y = Variable("Y".to_string());
+
+        // + and - are left-associative, ^ is right-associative and binds tighter than *
+        let expression = op("-", op("+", int(-1), op("*", int(2), int(3))), op("^", y, op("^", int(2), int(3))));
+        assert_eq!(body[0], Atom::new("is", vec![Variable("X".to_string()), expression]));
+        assert_eq!(body[1], Atom::new("=<", vec![Variable("X".to_string()), op("mod", int(10), int(4))]));
+    }
+
+    #[test]
+    fn test_parse_lists() {
+        let body = parse_query(tokenize("?- p([], [a], [1, X | T], [[b]|[]]).").unwrap()).unwrap();
+        let a = Constant("a".to_string());
+        let int = |n: i64| Term::Number(Number::Integer(n));
+        assert_eq!(body[0].args, vec![
+            Term::nil(),
+            Term::cons(a, Term::nil()),
+            Term::list(vec![int(1), Variable("X".to_string())], Variable("T".to_string())),
+            Term::cons(Term::cons(Constant("b".to_string()), Term::nil()), Term::nil()),
+        ]);
+
+        // list elements are parsed below the priority of ',' so operators can appear inside
+        let body = parse_query(tokenize("?- p([1 + 2, -3]).").unwrap()).unwrap();
+        let sum = Compound { name: "+".to_string(), args: vec![int(1), int(2)] };
+        assert_eq!(body[0].args, vec![Term::list(vec![sum, int(-3)], Term::nil())]);
+
+        assert!(parse_query(tokenize("?- p([a|]).").unwrap()).is_err());
+        assert!(parse_query(tokenize("?- p([a, b).").unwrap()).is_err());
+        assert!(parse_query(tokenize("?- p([a | b | c]).").unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_parse_operator_errors() {
+        // comparison operators are non-associative
+        assert!(parse_query(tokenize("?- 1 < 2 < 3.").unwrap()).is_err());
+        // a number is not a goal
+        assert!(parse_query(tokenize("?- 1.").unwrap()).is_err());
+        // dynamic has priority 1150, too high for an argument of =
+        assert!(parse_query(tokenize("?- X = dynamic a.").unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_parse_prefix_operator_priority() {
+        // the argument of a prefix operator is read up to the operator's own priority, commas included
+        let (stmts, errors) = Parser::from_source(":- dynamic a/1, b/2.").unwrap().parse_program();
+        assert!(errors.is_empty());
+        let Statement::Directive { body } = &stmts[0] else { panic!("Directive parsing failed") };
+        let indicator = |name: &str, arity: i64| Compound {
+            name: "/".to_string(),
+            args: vec![Constant(name.to_string()), Term::Number(Number::Integer(arity))],
+        };
+        let indicators = Compound { name: ",".to_string(), args: vec![indicator("a", 1), indicator("b", 2)] };
+        assert_eq!(body, &vec![Atom::new("dynamic", vec![indicators])]);
+    }
+
+    #[test]
+    fn test_parse_standard_operators() {
+        let body = parse_query(tokenize("?- X = (a :- b, c ; d), Y == - 1, T =.. [f|L], \\+ \\+ X @< -(1).").unwrap()).unwrap();
+        let op = |name: &str, args: Vec<Term>| Compound { name: name.to_string(), args };
+        let (a, b, c, d) = (Constant("a".to_string()), Constant("b".to_string()), Constant("c".to_string()), Constant("d".to_string()));
+        let int = |n: i64| Term::Number(Number::Integer(n));
+        let clause = op(":-", vec![a, op(";", vec![op(",", vec![b, c]), d])]);
+        assert_eq!(body[0], Atom::new("=", vec![Variable("X".to_string()), clause]));
+        // "- 1" is the prefix operator applied to 1, while "-1" and "-(1)" are a number and a compound term
+        assert_eq!(body[1].args[1], op("-", vec![int(1)]));
+        assert_eq!(body[2].name, "=..");
+        let not_not = op("\\+", vec![op("@<", vec![Variable("X".to_string()), op("-", vec![int(1)])])]);
+        assert_eq!(body[3], Atom::new("\\+", vec![not_not]));
+
+        // a | b between goals is a disjunction
+        let body = parse_query(tokenize("?- (a | b).").unwrap()).unwrap();
+        assert_eq!(body[0].name, ";");
+    }
+
+    #[test]
+    fn test_op_directives() {
+        let src = "a ==> b.\n:- op(700, xfx, ==>), op(200, xf, squared).\nrule(likes(X) ==> Y) :- p(X, Y).\nfact(3 squared).\n:- op(1201, xfx, bad).\n:- foo.";
+        let mut parser = Parser::from_source(src).unwrap();
+        let (stmts, errors) = parser.parse_program();
+        // ==> is not an operator before it is declared; invalid and unknown directives are reported when they run
+        assert_eq!(stmts.len(), 5);
+        let Statement::Directive { body } = &stmts[0] else { panic!("Directive parsing failed") };
+        assert_eq!(body.iter().map(|goal| goal.name.as_str()).collect::<Vec<_>>(), vec!["op", "op"]);
+        let Statement::Rule { head, .. } = &stmts[1] else { panic!("Rule parsing failed") };
+        let likes = Compound { name: "likes".to_string(), args: vec![Variable("X".to_string())] };
+        assert_eq!(head.args, vec![Compound { name: "==>".to_string(), args: vec![likes, Variable("Y".to_string())] }]);
+        let Statement::Fact(fact) = &stmts[2] else { panic!("Fact parsing failed") };
+        assert_eq!(fact.args, vec![Compound { name: "squared".to_string(), args: vec![Term::Number(Number::Integer(3))] }]);
+        assert!(matches!(&stmts[4], Statement::Directive { body } if body[0].name == "foo"));
+
+        let errors: Vec<String> = errors.iter().map(|e| e.to_string().lines().next().unwrap().to_string()).collect();
+        assert_eq!(errors, vec!["Syntax error: 1:3: Expected '.' or ':-' after atom, got '==>'"]);
+        assert!(parser.operators().infix("==>").is_some());
+
+        // a malformed op/3 does not declare any of its names
+        let mut parser = Parser::from_source(":- op(700, xfx, [===>, ',']).\np(a ===> b).").unwrap();
+        let (_, errors) = parser.parse_program();
+        assert_eq!(errors.len(), 1);
+        assert!(parser.operators().infix("===>").is_none());
+    }
+
+    #[test]
+    fn test_atom_spans() {
+        let (stmts, _) = Parser::from_source("p(a).\nq(X) :-\n    r(X), (s ; t),\n    u.").unwrap().parse_program();
+        let position = |atom: &Atom| (atom.span.line, atom.span.column, atom.span.end - atom.span.start);
+        let Statement::Rule { head, body } = &stmts[1] else { panic!("Rule parsing failed") };
+        assert_eq!(position(head), (2, 1, 4));
+        assert_eq!(body.iter().map(position).collect::<Vec<_>>(), vec![(3, 5, 4), (3, 11, 7), (4, 5, 1)]);
+        assert_eq!((stmts[1].span().line, stmts[1].span().column), (2, 1));
+        assert_eq!(stmts[1].span().end, body[2].span.end);
+    }
+
+    #[test]
+    fn test_term_spans() {
+        let (stmts, _) = Parser::from_source("p(f(X, -1), [a, b|T], - (1 + 2)).").unwrap().parse_program();
+        let Statement::Fact(head) = &stmts[0] else { panic!("Fact parsing failed") };
+        let position = |spans: &TermSpan| (spans.span.column, spans.span.end - spans.span.start);
+        let [f, list, minus] = &head.arg_spans[..] else { panic!("Expected three argument spans") };
+        assert_eq!(position(f), (3, 8));
+        assert_eq!(f.args.iter().map(position).collect::<Vec<_>>(), vec![(5, 1), (8, 2)]);
+        // each list cell spans from its element to the closing bracket
+        assert_eq!(position(list), (13, 8));
+        assert_eq!((position(&list.args[0]), position(&list.args[1])), ((14, 1), (17, 4)));
+        assert_eq!(position(&list.args[1].args[1]), (19, 1));
+        assert_eq!(position(&minus.args[0]), (25, 7));
+        assert_eq!(position(&minus.args[0].args[1]), (30, 1));
+    }
+
+    #[test]
+    fn test_variable_goals() {
+        let body = parse_query(tokenize("?- G, (X ; p).").unwrap()).unwrap();
+        assert_eq!(body[0], Atom::new("call", vec![Variable("G".to_string())]));
+        // inside control constructs a variable stays a term and is called when it is reached
+        assert_eq!(body[1].args[0], Variable("X".to_string()));
+    }
+
+    #[test]
+    fn test_parse_error_excerpt() {
+        let error = |src: &str| Parser::from_source(src).unwrap().parse_program().1[0].to_string();
+        assert_eq!(error("p(a) :- q(a\nr."), "Syntax error: 2:1: Expected ')', got 'r'\nr.\n^");
+        assert_eq!(error("p(a)"), "Syntax error: 1:5: Expected '.' or ':-' after atom, got end of input\np(a)\n    ^");
+        assert_eq!(error("p :- 1, q."), "Syntax error: 1:6: Expected goal\np :- 1, q.\n     ^");
+        assert_eq!(error("p([a,]).").lines().next(), Some("Syntax error: 1:6: Expected term, got ']'"));
+        // without the source only the position is reported
+        assert_eq!(parse_query(tokenize("?- p(.").unwrap()).unwrap_err().to_string(), "Syntax error: 1:6: Expected term, got '.'");
+        let Err(PrologError::Syntax(error)) = parse_query(tokenize("p(a).").unwrap()) else { panic!("not a syntax error") };
+        assert_eq!((error.message.as_str(), error.span.column), ("Expected query", 1));
+    }
+
+    #[test]
+    fn test_parse_program_recovers_from_lexical_errors() {
+        let src = "a.\nb.\nc.\nd :- 'x\\q'.\ne.\nf(X) :- X = [1 2].\ng :- 'abc.";
+        let (stmts, errors) = Parser::from_program(src).parse_program();
+        let heads: Vec<String> = stmts.iter().map(|stmt| match stmt {
+            Statement::Fact(head) => head.name.clone(),
+            _ => panic!("expected a fact, got {:?}", stmt),
+        }).collect();
+        assert_eq!(heads, vec!["a", "b", "c", "e"]);
+        let kinds: Vec<(bool, usize)> = errors.iter()
+            .map(|e| (matches!(e, PrologError::Lexical(_)), e.span().unwrap().line)).collect();
+        assert_eq!(kinds, vec![(true, 4), (false, 6), (true, 7)]);
+    }
+
+    #[test]
+    fn test_control_construct_heads() {
+        let (stmts, errors) = Parser::from_source("(a, b).\n(a ; b) :- c.\n(a -> b).\n!.\nd.").unwrap().parse_program();
+        assert_eq!(stmts.len(), 1);
+        let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(errors, vec![
+            "Syntax error: 1:1: Permission error: cannot modify static_procedure ','/2\n(a, b).\n^^^^^^",
+            "Syntax error: 2:1: Permission error: cannot modify static_procedure ;/2\n(a ; b) :- c.\n^^^^^^^",
+            "Syntax error: 3:1: Permission error: cannot modify static_procedure ->/2\n(a -> b).\n^^^^^^^^",
+            "Syntax error: 4:1: Permission error: cannot modify static_procedure !/0\n!.\n^",
+        ]);
+    }
+
+    #[test]
+    fn test_parse_program_recovers_from_errors() {
+        let src = "p(a).\nq(X :- r(X).\ns(b).\nt :- , u.\nv(c).\nw(";
+        let (stmts, errors) = Parser::from_source(src).unwrap().parse_program();
+        let heads: Vec<&str> = stmts
+            .iter()
+            .map(|stmt| match stmt {
+                Statement::Fact(head) | Statement::Rule { head, .. } => head.name.as_str(),
+                Statement::Query { .. } => "?-",
+                Statement::Directive { .. } => ":-",
+            })
+            .collect();
+        assert_eq!(heads, vec!["p", "s", "v"]);
+        let lines: Vec<usize> = errors.iter().map(|e| e.span().unwrap().line).collect();
+        assert_eq!(lines, vec![2, 4, 6]);
+        assert!(errors.iter().all(|e| matches!(e, PrologError::Syntax(_))));
+    }
+
+    #[test]
+    fn test_parse_quoted_atoms_and_strings() {
+        let body = parse_query(tokenize("?- 'New York'('X', [], 'is'), '\\\\+'(a).").unwrap()).unwrap();
+        assert_eq!(body[0], Atom::new("New York", vec![Constant("X".to_string()), Term::nil(), Constant("is".to_string())]));
+        assert_eq!(body[1].name, "\\+");
+
+        let read = |double_quotes: DoubleQuotes| {
+            let mut parser = Parser::from_source("p(\"ab\").").unwrap();
+            parser.set_double_quotes(double_quotes);
+            let (stmts, _) = parser.parse_program();
+            let Statement::Fact(head) = &stmts[0] else { panic!("Fact parsing failed") };
+            head.args[0].clone()
+        };
+        let int = |n: i64| Term::Number(Number::Integer(n));
+        assert_eq!(read(DoubleQuotes::default()), Term::list(vec![int(97), int(98)], Term::nil()));
+        assert_eq!(read(DoubleQuotes::Chars), Term::list(vec![Constant("a".to_string()), Constant("b".to_string())], Term::nil()));
+        assert_eq!(read(DoubleQuotes::Atom), Constant("ab".to_string()));
+        assert_eq!(read(DoubleQuotes::String), Term::String("ab".to_string()));
+    }
+
+    #[test]
+    fn test_set_prolog_flag_double_quotes() {
+        let src = "p(\"ab\").\n:- set_prolog_flag(double_quotes, chars).\nq(\"ab\").\n:- set_prolog_flag(double_quotes, atom).\nr(\"ab\").";
+        let mut parser = Parser::from_source(src).unwrap();
+        let (stmts, errors) = parser.parse_program();
+        assert!(errors.is_empty());
+        let args: Vec<Term> = stmts.iter().filter_map(|stmt| match stmt {
+            Statement::Fact(head) => Some(head.args[0].clone()),
+            _ => None,
+        }).collect();
+        let int = |n: i64| Term::Number(Number::Integer(n));
+        assert_eq!(args, vec![
+            Term::list(vec![int(97), int(98)], Term::nil()),
+            Term::list(vec![Constant("a".to_string()), Constant("b".to_string())], Term::nil()),
+            Constant("ab".to_string()),
+        ]);
+        assert_eq!(parser.double_quotes(), DoubleQuotes::Atom);
+    }
+
+    #[test]
+    fn test_singleton_warnings() {
+        let src = "p(X, Y) :- q(X).\nr(_, _Z, W, W).\ns(A, B, f(A, C)) :- t(B).\n?- u(V).";
+        let mut parser = Parser::from_source(src).unwrap();
+        let (stmts, errors) = parser.parse_program();
+        assert_eq!((stmts.len(), errors.len()), (4, 0));
+        let warnings: Vec<String> = parser.warnings().iter().map(|w| w.to_string()).collect();
+        assert_eq!(warnings, vec![
+            "1:1: Singleton variables: [Y]\np(X, Y) :- q(X).\n^^^^^^^^^^^^^^^",
+            "3:1: Singleton variables: [C]\ns(A, B, f(A, C)) :- t(B).\n^^^^^^^^^^^^^^^^^^^^^^^^",
+        ]);
+    }
+
+    #[test]
+    fn test_doc_comments_attach_to_next_clause() {
+        let src = "%! parent(?P, ?C)\n%% True if P is a parent of C.\nparent(a, b).\n% plain comment\nchild(C, P) :- parent(P, C).\n\n%! orphan\n";
+        let mut parser = Parser::from_source(src).unwrap();
+        let (stmts, errors) = parser.parse_program();
+        assert_eq!((stmts.len(), errors.len()), (2, 0));
+        assert_eq!(parser.doc_comments(), &[DocComment {
+            predicate: "parent/2".to_string(),
+            text: "parent(?P, ?C)\nTrue if P is a parent of C.".to_string(),
+        }]);
+    }
+
 }
@@ -1,6 +1,15 @@
-use crate::tokenizer::{Statement, Atom, Term};
-use crate::unificator::{Substitution, unify_atoms, unify_terms};
+use crate::tokenizer::{Statement, Atom, Term, Number, Span};
+use crate::arithmetic::{self, compare_numbers};
+use crate::database::{ClauseCursor, ClauseStore};
+use crate::error::{PrologError, SourceError};
+use crate::library;
+use crate::parser::DoubleQuotes;
+use crate::unificator::{Substitution, compare_terms, format_term, fresh_name, unify_atoms_with, unify_terms};
+use num_traits::Signed;
+use std::cmp::Ordering;
 use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::sync::OnceLock;
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct ResolutionNode {
@@ -9,68 +18,1165 @@ pub struct ResolutionNode {
     pub children: Vec<ResolutionNode>,
 }
 
-pub fn resolve_query(query: &[Atom], db: &Vec<Statement>) -> ResolutionNode {
-    fn resolve(
-        goal: &[Atom],
-        db: &Vec<Statement>,
-        subs: &mut Substitution,
-        counter: &mut usize,
-        current_goal: Option<Atom>,
-    ) -> ResolutionNode {
-        if goal.is_empty() {
-            return ResolutionNode {
-                goal: current_goal,
+// A goal waiting to be proven. `cut_barrier` is the height of the choicepoint stack when the clause
+// the goal came from was called; a cut in that clause removes every choicepoint above it.
+#[derive(Debug, Clone)]
+struct Goal {
+    atom: Atom,
+    cut_barrier: usize,
+}
+
+// The goals still to prove, as a linked list whose tail is shared by all the resolvents derived from it.
+// Replacing the first goal by a clause body only allocates the body, whatever the length of the continuation.
+#[derive(Debug, Clone, Default)]
+struct Goals(Option<Rc<GoalCell>>);
+
+#[derive(Debug)]
+struct GoalCell {
+    goal: Goal,
+    rest: Goals,
+}
+
+impl Goals {
+    // `goals` followed by `rest`
+    fn prepend(goals: impl DoubleEndedIterator<Item = Goal>, rest: Goals) -> Goals {
+        goals.rev().fold(rest, |rest, goal| Goals(Some(Rc::new(GoalCell { goal, rest }))))
+    }
+
+    fn first(&self) -> Option<&Goal> {
+        self.0.as_ref().map(|cell| &cell.goal)
+    }
+
+    // The goals after the first one
+    fn rest(&self) -> Goals {
+        self.0.as_ref().map(|cell| cell.rest.clone()).unwrap_or_default()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_none()
+    }
+}
+
+// Frees the cells one by one, since dropping a long list recursively would overflow the call stack
+impl Drop for Goals {
+    fn drop(&mut self) {
+        let mut next = self.0.take();
+        while let Some(cell) = next {
+            next = Rc::try_unwrap(cell).ok().and_then(|mut cell| cell.rest.0.take());
+        }
+    }
+}
+
+// A resumable point in the SLD derivation: the goals still to prove, the bindings so far
+// and, once the first goal has been called, where its search for clauses resumes.
+#[derive(Debug, Clone)]
+struct ChoicePoint {
+    goals: Goals,
+    subs: Substitution,
+    clause: Option<ClauseCursor>,
+    node: Vec<usize>, // path of the matching node in the traced tree
+}
+
+impl ChoicePoint {
+    // The goal to prove next; only steps with goals left ask for it
+    fn goal(&self) -> &Goal {
+        self.goals.first().expect("a resolvent with goals left")
+    }
+}
+
+/// Lazy stream of answers to a query, produced by depth-first SLD resolution.
+///
+/// Alternatives are kept on an explicit choicepoint stack instead of the call stack,
+/// so answers are computed one at a time and infinite answer sets can be cut short.
+pub struct Solutions {
+    db: ClauseStore,
+    query_vars: Vec<String>,
+    stack: Vec<ChoicePoint>,
+    counter: usize,
+    trace: Option<Trace>,
+    error_goal: Option<(Atom, Substitution)>, // the goal that raised the last error, with its bindings
+}
+
+// A builtin predicate proves the first goal of a choicepoint itself instead of resolving it against clauses
+type Builtin = fn(&mut Solutions, ChoicePoint) -> Result<(), PrologError>;
+
+// The builtin predicates by name and arity, grouped by the step that executes them
+const BUILTINS: &[(Builtin, &[(&str, usize)])] = &[
+    (Solutions::step_control, &[("!", 0), ("true", 0), ("fail", 0), ("false", 0), ("\\+", 1), ("not", 1), (",", 2), (";", 2), ("->", 2), ("^", 2)]),
+    (Solutions::step_call, &[("call", 1), ("call", 2), ("call", 3), ("call", 4), ("call", 5), ("call", 6), ("call", 7), ("call", 8)]),
+    (Solutions::step_arithmetic, &[("is", 2), ("<", 2), (">", 2), ("=<", 2), (">=", 2), ("=:=", 2), ("=\\=", 2)]),
+    (Solutions::step_unify, &[("=", 2), ("\\=", 2)]),
+    (Solutions::step_compare, &[("==", 2), ("\\==", 2), ("@<", 2), ("@>", 2), ("@=<", 2), ("@>=", 2), ("compare", 3)]),
+    (Solutions::step_type_check, &[
+        ("var", 1), ("nonvar", 1), ("atom", 1), ("number", 1), ("integer", 1), ("float", 1), ("string", 1),
+        ("compound", 1), ("atomic", 1), ("callable", 1), ("is_list", 1), ("ground", 1),
+    ]),
+    (Solutions::step_functor, &[("functor", 3)]),
+    (Solutions::step_arg, &[("arg", 3)]),
+    (Solutions::step_univ, &[("=..", 2)]),
+    (Solutions::step_copy_term, &[("copy_term", 2)]),
+    (Solutions::step_findall, &[("findall", 3), ("findall", 4)]),
+    (Solutions::step_bagof, &[("bagof", 3), ("setof", 3)]),
+    (Solutions::step_aggregate_all, &[("aggregate_all", 3)]),
+    (Solutions::step_assert, &[("assert", 1), ("asserta", 1), ("assertz", 1)]),
+    (Solutions::step_retract, &[("retract", 1)]),
+    (Solutions::step_retractall, &[("retractall", 1)]),
+    (Solutions::step_abolish, &[("abolish", 1)]),
+    (Solutions::step_length, &[("length", 2)]),
+    (Solutions::step_sort, &[("msort", 2), ("sort", 2)]),
+    (Solutions::step_declaration, &[
+        ("op", 3),  ("dynamic", 1), ("discontiguous", 1), ("ensure_loaded", 1), ("use_module", 1), ("use_module", 2),
+        ("initialization", 1), ("set_prolog_flag", 2),
+    ]),
+];
+
+fn builtin(name: &str, arity: usize) -> Option<Builtin> {
+    static REGISTRY: OnceLock<HashMap<(&str, usize), Builtin>> = OnceLock::new();
+    let registry = REGISTRY.get_or_init(|| {
+        BUILTINS.iter().flat_map(|&(step, predicates)| predicates.iter().map(move |&key| (key, step))).collect()
+    });
+    registry.get(&(name, arity)).copied()
+}
+
+struct Trace {
+    root: ResolutionNode,
+    successes: HashSet<Vec<usize>>,
+}
+
+pub fn solve(query: &[Atom], db: impl Into<ClauseStore>) -> Solutions {
+    let mut counter = 0;
+    Solutions {
+        db: db.into(),
+        query_vars: get_query_vars(query),
+        stack: vec![ChoicePoint {
+            goals: Goals::prepend(
+                query.iter().map(|atom| Goal { atom: rename_anonymous(atom, &mut counter), cut_barrier: 0 }),
+                Goals::default(),
+            ),
+            subs: Substitution::new(),
+            clause: None,
+            node: vec![],
+        }],
+        counter,
+        trace: None,
+        error_goal: None,
+    }
+}
+
+impl Solutions {
+    /// Records every successful resolution step so that the tree can be recovered with `into_tree`.
+    pub fn traced(mut self) -> Self {
+        self.trace = Some(Trace {
+            root: ResolutionNode { goal: None, subs: Substitution::new(), children: vec![] },
+            successes: HashSet::new(),
+        });
+        self
+    }
+
+    pub fn query_vars(&self) -> &[String] {
+        &self.query_vars
+    }
+
+    /// True when no alternatives are left, i.e. the last answer returned was the final one.
+    pub fn is_exhausted(&self) -> bool {
+        self.stack.is_empty()
+    }
+
+    /// Returns the resolution tree explored so far, keeping only the branches that led to an answer.
+    pub fn into_tree(self) -> Option<ResolutionNode> {
+        fn prune(node: ResolutionNode, path: &mut Vec<usize>, successes: &HashSet<Vec<usize>>) -> Option<ResolutionNode> {
+            let mut children = vec![];
+            for (i, child) in node.children.into_iter().enumerate() {
+                path.push(i);
+                if let Some(child) = prune(child, path, successes) {
+                    children.push(child);
+                }
+                path.pop();
+            }
+            if children.is_empty() && !successes.contains(path) {
+                return None;
+            }
+            Some(ResolutionNode { goal: node.goal, subs: node.subs, children })
+        }
+
+        let trace = self.trace?;
+        let root = prune(trace.root, &mut vec![], &trace.successes);
+        Some(root.unwrap_or(ResolutionNode { goal: None, subs: Substitution::new(), children: vec![] }))
+    }
+
+    fn trace_step(&mut self, parent: &[usize], goal: &Atom, subs: &Substitution) -> Vec<usize> {
+        let Some(trace) = self.trace.as_mut() else { return vec![] };
+        let mut node = &mut trace.root;
+        for &i in parent {
+            node = &mut node.children[i];
+        }
+        node.children.push(ResolutionNode { goal: Some(goal.clone()), subs: subs.clone(), children: vec![] });
+        let mut path = parent.to_vec();
+        path.push(node.children.len() - 1);
+        path
+    }
+
+    // A separate derivation of `goal` under the given bindings, sharing the database and the variable counter.
+    // Choicepoints of the inner derivation, including those a cut would remove, stay local to it.
+    fn derivation(&self, goal: Atom, subs: &Substitution) -> Solutions {
+        Solutions {
+            db: self.db.clone(),
+            query_vars: vec![],
+            stack: vec![ChoicePoint {
+                goals: Goals::prepend(std::iter::once(Goal { atom: goal, cut_barrier: 0 }), Goals::default()),
                 subs: subs.clone(),
-                children: vec![],
+                clause: None,
+                node: vec![],
+            }],
+            counter: self.counter,
+            trace: None,
+            error_goal: None,
+        }
+    }
+
+    // Proves `goal` in a separate derivation and returns the bindings of its first solution
+    fn solve_once(&mut self, goal: Atom, subs: &Substitution) -> Result<Option<Substitution>, PrologError> {
+        let mut inner = self.derivation(goal, subs);
+        let result = inner.next_subs();
+        self.counter = inner.counter;
+        result.transpose()
+    }
+
+    // Proves `goal` in a separate derivation and returns the bindings of all its solutions, in order
+    fn solve_all(&mut self, goal: Atom, subs: &Substitution) -> Result<Vec<Substitution>, PrologError> {
+        let mut inner = self.derivation(goal, subs);
+        let result = std::iter::from_fn(|| inner.next_subs()).collect();
+        self.counter = inner.counter;
+        result
+    }
+
+    /// Where the goal that raised `error` was read, narrowed down to the argument subterm the error is about
+    /// when it is found there. None if that goal was not read from the source.
+    pub fn error_span(&self, error: &PrologError) -> Option<Span> {
+        let (goal, subs) = self.error_goal.as_ref()?;
+        let culprit = match error {
+            PrologError::Type { culprit, .. }
+            | PrologError::Domain { culprit, .. }
+            | PrologError::Existence { culprit, .. }
+            | PrologError::Permission { culprit, .. } => Some(culprit),
+            _ => None,
+        };
+        let args: Vec<Term> = goal.args.iter().map(|arg| apply_subs(arg, subs)).collect();
+        // the culprit is the term itself or, for an unknown evaluable or procedure, its Name/Arity
+        let found = culprit.and_then(|culprit| goal.find_span(&args, |term| {
+            let indicator = match term {
+                Term::Constant(name) => Some(format!("{}/0", name)),
+                Term::Compound { name, args } => Some(format!("{}/{}", name, args.len())),
+                _ => None,
             };
+            format_term(term) == *culprit || indicator.is_some_and(|indicator| indicator == *culprit)
+        }));
+        found.or(Some(goal.span).filter(|&span| span != Span::default()))
+    }
+
+    // Runs the derivation until the next solution and returns its complete substitution.
+    // An error aborts the whole derivation.
+    fn next_subs(&mut self) -> Option<Result<Substitution, PrologError>> {
+        while let Some(cp) = self.stack.pop() {
+            if cp.goals.is_empty() {
+                if let Some(trace) = self.trace.as_mut() {
+                    trace.successes.insert(cp.node);
+                }
+                return Some(Ok(cp.subs));
+            }
+            let (goals, subs) = (cp.goals.clone(), cp.subs.clone());
+            if let Err(e) = self.step(cp) {
+                self.error_goal = goals.first().map(|goal| (goal.atom.clone(), subs));
+                self.stack.clear();
+                return Some(Err(e));
+            }
+        }
+        None
+    }
+
+    // Runs a builtin on the first goal of `cp` if there is one for its name and arity, otherwise resolves it
+    // against the clauses of the database
+    fn step(&mut self, cp: ChoicePoint) -> Result<(), PrologError> {
+        let goal = &cp.goal().atom;
+        match builtin(&goal.name, goal.args.len()) {
+            Some(step) => step(self, cp),
+            None => self.step_clauses(cp),
+        }
+    }
+
+    // Executes the type checks, which succeed without binding anything when their argument has the given type
+    fn step_type_check(&mut self, cp: ChoicePoint) -> Result<(), PrologError> {
+        let goal = &cp.goal().atom;
+        let term = apply_subs(&goal.args[0], &cp.subs);
+        let succeeded = match goal.name.as_str() {
+            "var" => matches!(term, Term::Variable(_)),
+            "nonvar" => !matches!(term, Term::Variable(_)),
+            "atom" => matches!(term, Term::Constant(_)),
+            "number" => matches!(term, Term::Number(_)),
+            "integer" => matches!(term, Term::Number(Number::Integer(_) | Number::BigInt(_))),
+            "float" => matches!(term, Term::Number(Number::Float(_))),
+            "string" => matches!(term, Term::String(_)),
+            "compound" => matches!(term, Term::Compound { .. }),
+            "atomic" => matches!(term, Term::Constant(_) | Term::Number(_) | Term::String(_)),
+            "callable" => matches!(term, Term::Constant(_) | Term::Compound { .. }),
+            "is_list" => *term.list_items().1 == Term::nil(),
+            "ground" => {
+                let mut vars = HashSet::new();
+                get_term_vars(&term, &mut vars);
+                vars.is_empty()
+            }
+            _ => unreachable!("not a type check: {}", goal.name),
+        };
+        if succeeded {
+            let node = self.trace_step(&cp.node, goal, &cp.subs);
+            self.push_resolvent(&cp, vec![], cp.subs.clone(), node);
         }
+        Ok(())
+    }
 
-        let first = &goal[0];
-        let rest = &goal[1..];
+    // Executes is/2 and the arithmetic comparison predicates
+    fn step_arithmetic(&mut self, cp: ChoicePoint) -> Result<(), PrologError> {
+        let goal = &cp.goal().atom;
+        let (left, right) = (&goal.args[0], &goal.args[1]);
+        let mut subs = cp.subs.clone();
 
-        let mut children_nodes = vec![];
+        let succeeded = if goal.name == "is" {
+            let value = Term::Number(arithmetic::evaluate(right, &subs)?);
+            unify_terms(left, &value, &mut subs)
+        } else {
+            let ordering = arithmetic::compare(left, right, &subs)?;
+            match goal.name.as_str() {
+                "<" => ordering == Some(Ordering::Less),
+                ">" => ordering == Some(Ordering::Greater),
+                "=<" => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+                ">=" => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+                "=:=" => ordering == Some(Ordering::Equal),
+                _ => ordering != Some(Ordering::Equal),
+            }
+        };
 
-        for stmt in db {
-            match stmt {
-                Statement::Fact(fact) => {
-                    let mut new_subs = subs.clone();
-                    if let Some(s) = unify_atoms(first, fact) {
-                        for (k, v) in s {
-                            new_subs.insert(k, v);
-                        }
-                        let child = resolve(rest, db, &mut new_subs, counter, Some(fact.clone()));
-                        children_nodes.push(child);
-                    }
+        if succeeded {
+            let node = self.trace_step(&cp.node, goal, &subs);
+            self.push_resolvent(&cp, vec![], subs, node);
+        }
+        Ok(())
+    }
+
+    // X = Y unifies the two terms; X \= Y succeeds, without binding anything, when they do not unify
+    fn step_unify(&mut self, cp: ChoicePoint) -> Result<(), PrologError> {
+        let goal = &cp.goal().atom;
+        let mut subs = cp.subs.clone();
+        let unified = unify_terms(&goal.args[0], &goal.args[1], &mut subs);
+        let subs = match (goal.name.as_str(), unified) {
+            ("=", true) => subs,
+            ("\\=", false) => cp.subs.clone(),
+            _ => return Ok(()),
+        };
+        let node = self.trace_step(&cp.node, goal, &subs);
+        self.push_resolvent(&cp, vec![], subs, node);
+        Ok(())
+    }
+
+    // Compares two terms in the standard order without binding them: ==, \==, @<, @>, @=<, @>= and
+    // compare(Order, X, Y), which unifies Order with <, = or >
+    fn step_compare(&mut self, cp: ChoicePoint) -> Result<(), PrologError> {
+        let goal = &cp.goal().atom;
+        let mut subs = cp.subs.clone();
+        let (left, right) = match goal.args.as_slice() {
+            [_, left, right] => (left, right),
+            [left, right] => (left, right),
+            _ => unreachable!("comparisons have two terms"),
+        };
+        let ordering = compare_terms(&apply_subs(left, &subs), &apply_subs(right, &subs));
+
+        let succeeded = match goal.name.as_str() {
+            "==" => ordering.is_eq(),
+            "\\==" => ordering.is_ne(),
+            "@<" => ordering.is_lt(),
+            "@>" => ordering.is_gt(),
+            "@=<" => ordering.is_le(),
+            "@>=" => ordering.is_ge(),
+            _ => {
+                let order = apply_subs(&goal.args[0], &subs);
+                match &order {
+                    Term::Variable(_) => {}
+                    Term::Constant(name) if matches!(name.as_str(), "<" | "=" | ">") => {}
+                    Term::Constant(_) => return Err(PrologError::domain_error("order", format_term(&order))),
+                    _ => return Err(PrologError::type_error("atom", format_term(&order))),
                 }
-                Statement::Rule { head, body } => {
-                    // Freshen rule consistently
-                    let (fresh_head, fresh_body) = fresh_rule(head, body, counter);
-
-                    let mut new_subs = subs.clone();
-                    if let Some(s) = unify_atoms(first, &fresh_head) {
-                        for (k, v) in s {
-                            new_subs.insert(k, v);
-                        }
-
-                        let mut new_goals = fresh_body;
-                        new_goals.extend_from_slice(rest);
-                        let child = resolve(&new_goals, db, &mut new_subs, counter, Some(fresh_head));
-                        children_nodes.push(child);
+                let symbol = match ordering {
+                    Ordering::Less => "<",
+                    Ordering::Equal => "=",
+                    Ordering::Greater => ">",
+                };
+                unify_terms(&order, &Term::Constant(symbol.to_string()), &mut subs)
+            }
+        };
+
+        if succeeded {
+            let node = self.trace_step(&cp.node, goal, &subs);
+            self.push_resolvent(&cp, vec![], subs, node);
+        }
+        Ok(())
+    }
+
+    // functor(Term, Name, Arity): the name and arity of a term, or a term with fresh arguments built from them.
+    // Atomic terms, and compound terms without arguments, are their own name with arity 0.
+    fn step_functor(&mut self, cp: ChoicePoint) -> Result<(), PrologError> {
+        let goal = &cp.goal().atom;
+        let args: Vec<Term> = goal.args.iter().map(|arg| apply_subs(arg, &cp.subs)).collect();
+        let mut subs = cp.subs.clone();
+        let succeeded = match &args[0] {
+            Term::Compound { name, args: items } if !items.is_empty() => {
+                let arity = Term::Number(Number::Integer(items.len() as i64));
+                unify_terms(&args[1], &Term::Constant(name.clone()), &mut subs) && unify_terms(&args[2], &arity, &mut subs)
+            }
+            Term::Compound { name, .. } => {
+                unify_terms(&args[1], &Term::Constant(name.clone()), &mut subs)
+                    && unify_terms(&args[2], &Term::Number(Number::Integer(0)), &mut subs)
+            }
+            Term::Constant(_) | Term::Number(_) | Term::String(_) => {
+                unify_terms(&args[1], &args[0], &mut subs) && unify_terms(&args[2], &Term::Number(Number::Integer(0)), &mut subs)
+            }
+            Term::Variable(_) => {
+                let arity = match &args[2] {
+                    Term::Variable(_) => return Err(PrologError::Instantiation),
+                    Term::Number(Number::Integer(n)) if *n >= 0 => *n as usize,
+                    Term::Number(Number::Integer(_)) => return Err(PrologError::domain_error("not_less_than_zero", format_term(&args[2]))),
+                    Term::Number(Number::BigInt(n)) if n.is_negative() => return Err(PrologError::domain_error("not_less_than_zero", format_term(&args[2]))),
+                    Term::Number(Number::BigInt(_)) => return Err(PrologError::resource_error("memory")),
+                    _ => return Err(PrologError::type_error("integer", format_term(&args[2]))),
+                };
+                let term = match (&args[1], arity) {
+                    (Term::Variable(_), _) => return Err(PrologError::Instantiation),
+                    (Term::Compound { .. }, _) => return Err(PrologError::type_error("atomic", format_term(&args[1]))),
+                    (name, 0) => name.clone(),
+                    (Term::Constant(name), _) => Term::Compound { name: name.clone(), args: (0..arity).map(|_| self.fresh_var()).collect() },
+                    (name, _) => return Err(PrologError::type_error("atom", format_term(name))),
+                };
+                unify_terms(&args[0], &term, &mut subs)
+            }
+        };
+
+        if succeeded {
+            let node = self.trace_step(&cp.node, goal, &subs);
+            self.push_resolvent(&cp, vec![], subs, node);
+        }
+        Ok(())
+    }
+
+    // arg(N, Term, Arg): the N-th argument of a compound term; with N unbound it enumerates the arguments
+    fn step_arg(&mut self, cp: ChoicePoint) -> Result<(), PrologError> {
+        let goal = &cp.goal().atom;
+        let args: Vec<Term> = goal.args.iter().map(|arg| apply_subs(arg, &cp.subs)).collect();
+        let items = match &args[1] {
+            Term::Variable(_) => return Err(PrologError::Instantiation),
+            Term::Compound { args, .. } => args,
+            term => return Err(PrologError::type_error("compound", format_term(term))),
+        };
+        let positions: Vec<usize> = match &args[0] {
+            Term::Variable(_) => (1..=items.len()).collect(),
+            Term::Number(Number::Integer(n)) if (1..=items.len() as i64).contains(n) => vec![*n as usize],
+            Term::Number(Number::Integer(_) | Number::BigInt(_)) => vec![],
+            term => return Err(PrologError::type_error("integer", format_term(term))),
+        };
+
+        // pushed last to first, so that the first argument is tried first
+        for &position in positions.iter().rev() {
+            let mut subs = cp.subs.clone();
+            let index = Term::Number(Number::Integer(position as i64));
+            if unify_terms(&args[0], &index, &mut subs) && unify_terms(&args[2], &items[position - 1], &mut subs) {
+                let node = self.trace_step(&cp.node, goal, &subs);
+                self.push_resolvent(&cp, vec![], subs, node);
+            }
+        }
+        Ok(())
+    }
+
+    // Term =.. List ("univ"): converts between a term and the list of its name followed by its arguments
+    fn step_univ(&mut self, cp: ChoicePoint) -> Result<(), PrologError> {
+        let goal = &cp.goal().atom;
+        let term = apply_subs(&goal.args[0], &cp.subs);
+        let list = apply_subs(&goal.args[1], &cp.subs);
+        let mut subs = cp.subs.clone();
+        let succeeded = match &term {
+            Term::Compound { name, args } => {
+                let items = std::iter::once(Term::Constant(name.clone())).chain(args.iter().cloned()).collect();
+                unify_terms(&list, &Term::list(items, Term::nil()), &mut subs)
+            }
+            Term::Constant(_) | Term::Number(_) | Term::String(_) => {
+                unify_terms(&list, &Term::list(vec![term.clone()], Term::nil()), &mut subs)
+            }
+            Term::Variable(_) => {
+                let (items, tail) = list.list_items();
+                match tail {
+                    Term::Variable(_) => return Err(PrologError::Instantiation),
+                    tail if *tail != Term::nil() => return Err(PrologError::type_error("list", format_term(&list))),
+                    _ => {}
+                }
+                let built = match items.as_slice() {
+                    [] => return Err(PrologError::domain_error("non_empty_list", "[]")),
+                    [Term::Variable(_), ..] => return Err(PrologError::Instantiation),
+                    [Term::Compound { .. }, ..] => return Err(PrologError::type_error("atomic", format_term(items[0]))),
+                    [atomic] => (*atomic).clone(),
+                    [Term::Constant(name), args @ ..] => {
+                        Term::Compound { name: name.clone(), args: args.iter().map(|&arg| arg.clone()).collect() }
                     }
+                    [name, ..] => return Err(PrologError::type_error("atom", format_term(name))),
+                };
+                unify_terms(&term, &built, &mut subs)
+            }
+        };
+
+        if succeeded {
+            let node = self.trace_step(&cp.node, goal, &subs);
+            self.push_resolvent(&cp, vec![], subs, node);
+        }
+        Ok(())
+    }
+
+    // copy_term(Term, Copy): unifies Copy with Term in which every variable is replaced by a fresh one
+    fn step_copy_term(&mut self, cp: ChoicePoint) -> Result<(), PrologError> {
+        let goal = &cp.goal().atom;
+        let copy = self.copy_term(&apply_subs(&goal.args[0], &cp.subs));
+        let mut subs = cp.subs.clone();
+        if unify_terms(&goal.args[1], &copy, &mut subs) {
+            let node = self.trace_step(&cp.node, goal, &subs);
+            self.push_resolvent(&cp, vec![], subs, node);
+        }
+        Ok(())
+    }
+
+    // findall(Template, Goal, List) collects a copy of Template for every solution of Goal, in order;
+    // findall/4 ends the list with a given tail instead of []
+    fn step_findall(&mut self, cp: ChoicePoint) -> Result<(), PrologError> {
+        let goal = &cp.goal().atom;
+        check_partial_list(&apply_subs(&goal.args[2], &cp.subs))?;
+        let inner = body_goal(&goal.args[1], &cp.subs)?;
+        let solutions = self.solve_all(inner, &cp.subs)?;
+        let instances = solutions.iter().map(|subs| self.copy_term(&apply_subs(&goal.args[0], subs))).collect();
+        let tail = goal.args.get(3).cloned().unwrap_or_else(Term::nil);
+
+        let mut subs = cp.subs.clone();
+        if unify_terms(&goal.args[2], &Term::list(instances, tail), &mut subs) {
+            let node = self.trace_step(&cp.node, goal, &subs);
+            self.push_resolvent(&cp, vec![], subs, node);
+        }
+        Ok(())
+    }
+
+    // bagof(Template, Goal, Bag) is like findall/3, but fails when Goal has no solution and groups the solutions
+    // by the bindings of the free variables of Goal: those that occur neither in Template nor before a ^ in
+    // V^Goal. Each group is an answer on backtracking, in the standard order of the bindings.
+    // setof/3 also sorts each bag and removes duplicates.
+    fn step_bagof(&mut self, cp: ChoicePoint) -> Result<(), PrologError> {
+        let goal = &cp.goal().atom;
+        check_partial_list(&apply_subs(&goal.args[2], &cp.subs))?;
+        let template = apply_subs(&goal.args[0], &cp.subs);
+        let mut inner = apply_subs(&goal.args[1], &cp.subs);
+        let mut bound = term_variables(&template);
+        while let Term::Compound { name, args } = &inner {
+            if name != "^" || args.len() != 2 {
+                break;
+            }
+            bound.extend(term_variables(&args[0]));
+            inner = args[1].clone();
+        }
+        let free: Vec<Term> = term_variables(&inner)
+            .into_iter()
+            .filter(|var| !bound.contains(var))
+            .map(Term::Variable)
+            .collect();
+        let witness = Term::Compound { name: "v".to_string(), args: free };
+
+        let solutions = self.solve_all(body_goal(&inner, &cp.subs)?, &cp.subs)?;
+        let mut pairs: Vec<(Term, Term)> = solutions
+            .iter()
+            .map(|subs| {
+                let pair = Term::Compound { name: "-".to_string(), args: vec![apply_subs(&witness, subs), apply_subs(&template, subs)] };
+                match self.copy_term(&pair) {
+                    Term::Compound { mut args, .. } => (args.remove(0), args.remove(0)),
+                    _ => unreachable!("a copy of a pair is a pair"),
                 }
-                _ => {}
+            })
+            .collect();
+        pairs.sort_by(|(a, _), (b, _)| compare_terms(a, b));
+
+        // As in ISO, each group takes the first bindings left together with every variant of them in the rest of
+        // the solutions. Variants need not be neighbours after sorting when their variables are in different places.
+        let mut groups: Vec<(Vec<Term>, Vec<Term>)> = vec![];
+        while !pairs.is_empty() {
+            let first = pairs[0].0.clone();
+            let (group, rest): (Vec<_>, Vec<_>) = pairs.into_iter().partition(|(key, _)| is_variant(&first, key));
+            groups.push(group.into_iter().unzip());
+            pairs = rest;
+        }
+
+        // pushed last to first, so that the first group is tried first
+        for (keys, mut items) in groups.into_iter().rev() {
+            if goal.name == "setof" {
+                items.sort_by(compare_terms);
+                items.dedup_by(|a, b| compare_terms(a, b).is_eq());
+            }
+            let mut subs = cp.subs.clone();
+            if keys.iter().all(|key| unify_terms(key, &witness, &mut subs))
+                && unify_terms(&goal.args[2], &Term::list(items, Term::nil()), &mut subs)
+            {
+                let node = self.trace_step(&cp.node, goal, &subs);
+                self.push_resolvent(&cp, vec![], subs, node);
             }
         }
+        Ok(())
+    }
+
+    // A copy of `term` with every variable replaced by a fresh one
+    fn copy_term(&mut self, term: &Term) -> Term {
+        let (copy, _) = fresh_rule(&Atom::new("copy_term", vec![term.clone()]), &[], &mut self.counter);
+        copy.args.into_iter().next().expect("the copy has the term as its argument")
+    }
+
+    // aggregate_all(Spec, Goal, Result) aggregates over all solutions of Goal: count, count(T), sum(Expr),
+    // max(Expr), min(Expr), bag(Template) or set(Template). max and min fail when Goal has no solution.
+    fn step_aggregate_all(&mut self, cp: ChoicePoint) -> Result<(), PrologError> {
+        let goal = &cp.goal().atom;
+        let spec = apply_subs(&goal.args[0], &cp.subs);
+        let (kind, template) = match &spec {
+            Term::Variable(_) => return Err(PrologError::Instantiation),
+            Term::Constant(name) if name == "count" => (name.as_str(), None),
+            Term::Compound { name, args } if args.len() == 1 && matches!(name.as_str(), "count" | "sum" | "max" | "min" | "bag" | "set") => {
+                (name.as_str(), Some(&args[0]))
+            }
+            _ => return Err(PrologError::domain_error("aggregate_spec", format_term(&spec))),
+        };
+        let inner = body_goal(&goal.args[1], &cp.subs)?;
+        let solutions = self.solve_all(inner, &cp.subs)?;
+        let instances = || solutions.iter().map(|subs| apply_subs(template.expect("the spec has a template"), subs));
 
-        ResolutionNode {
-            goal: current_goal,
-            subs: subs.clone(),
-            children: children_nodes,
+        let result = match kind {
+            "count" => Term::Number(Number::Integer(solutions.len() as i64)),
+            "bag" => Term::list(instances().collect(), Term::nil()),
+            "set" => {
+                let mut set: Vec<Term> = instances().collect();
+                set.sort_by(compare_terms);
+                set.dedup_by(|a, b| compare_terms(a, b).is_eq());
+                Term::list(set, Term::nil())
+            }
+            _ => {
+                let mut values = vec![];
+                for term in instances() {
+                    values.push(arithmetic::evaluate(&term, &cp.subs)?);
+                }
+                let extreme = if kind == "max" { Ordering::Greater } else { Ordering::Less };
+                let aggregate = match kind {
+                    "sum" => values.into_iter().try_fold(Number::Integer(0), |sum, value| {
+                        let sum = Term::Compound { name: "+".to_string(), args: vec![Term::Number(sum), Term::Number(value)] };
+                        arithmetic::evaluate(&sum, &cp.subs)
+                    })?,
+                    _ => match values.into_iter().reduce(|best, value| {
+                        if compare_numbers(&value, &best) == Some(extreme) { value } else { best }
+                    }) {
+                        Some(best) => best,
+                        None => return Ok(()),
+                    },
+                };
+                Term::Number(aggregate)
+            }
+        };
+
+        let mut subs = cp.subs.clone();
+        if unify_terms(&goal.args[2], &result, &mut subs) {
+            let node = self.trace_step(&cp.node, goal, &subs);
+            self.push_resolvent(&cp, vec![], subs, node);
         }
+        Ok(())
     }
 
-    let mut counter = 0;
-    resolve(query, db, &mut Substitution::new(), &mut counter, None)
+    // length(List, Length): measures a proper list, or builds or enumerates partial lists of a given or growing length
+    fn step_length(&mut self, cp: ChoicePoint) -> Result<(), PrologError> {
+        let goal = &cp.goal().atom;
+        let list = apply_subs(&goal.args[0], &cp.subs);
+        let length = apply_subs(&goal.args[1], &cp.subs);
+        let (items, tail) = list.list_items();
+        let known = items.len();
+
+        let required = match &length {
+            Term::Variable(_) => None,
+            Term::Number(Number::Integer(n)) if *n >= 0 => Some(*n as usize),
+            Term::Number(Number::Integer(_)) => return Err(PrologError::domain_error("not_less_than_zero", format_term(&length))),
+            Term::Number(Number::BigInt(n)) if n.is_negative() => return Err(PrologError::domain_error("not_less_than_zero", format_term(&length))),
+            Term::Number(Number::BigInt(_)) => return Err(PrologError::resource_error("memory")),
+            _ => return Err(PrologError::type_error("integer", format_term(&length))),
+        };
+
+        let mut subs = cp.subs.clone();
+        let succeeded = match (tail, required) {
+            (Term::Constant(nil), _) if nil == "[]" => {
+                unify_terms(&length, &Term::Number(Number::Integer(known as i64)), &mut subs)
+            }
+            (Term::Variable(_), Some(n)) => {
+                let cells = (known..n).map(|_| self.fresh_var()).collect();
+                n >= known && unify_terms(tail, &Term::list(cells, Term::nil()), &mut subs)
+            }
+            // The alternative retries the goal with the open tail extended by one more cell
+            (Term::Variable(_), None) => {
+                let mut longer = cp.subs.clone();
+                unify_terms(tail, &Term::cons(self.fresh_var(), self.fresh_var()), &mut longer);
+                self.stack.push(ChoicePoint { subs: longer, ..cp.clone() });
+                unify_terms(tail, &Term::nil(), &mut subs)
+                    && unify_terms(&length, &Term::Number(Number::Integer(known as i64)), &mut subs)
+            }
+            _ => return Err(PrologError::type_error("list", format_term(&list))),
+        };
+
+        if succeeded {
+            let node = self.trace_step(&cp.node, goal, &subs);
+            self.push_resolvent(&cp, vec![], subs, node);
+        }
+        Ok(())
+    }
+
+    // msort(List, Sorted) sorts a proper list in the standard order of terms; sort/2 also removes duplicates
+    fn step_sort(&mut self, cp: ChoicePoint) -> Result<(), PrologError> {
+        let goal = &cp.goal().atom;
+        let list = apply_subs(&goal.args[0], &cp.subs);
+        let (items, tail) = list.list_items();
+        match tail {
+            Term::Variable(_) => return Err(PrologError::Instantiation),
+            tail if *tail != Term::nil() => return Err(PrologError::type_error("list", format_term(&list))),
+            _ => {}
+        }
+
+        let mut sorted: Vec<Term> = items.into_iter().cloned().collect();
+        sorted.sort_by(compare_terms);
+        if goal.name == "sort" {
+            sorted.dedup_by(|a, b| compare_terms(a, b).is_eq());
+        }
+
+        let mut subs = cp.subs.clone();
+        if unify_terms(&goal.args[1], &Term::list(sorted, Term::nil()), &mut subs) {
+            let node = self.trace_step(&cp.node, goal, &subs);
+            self.push_resolvent(&cp, vec![], subs, node);
+        }
+        Ok(())
+    }
+
+    // The predicates used in directives. op/3 and set_prolog_flag/2 change the operator table and the
+    // double_quotes flag of the database, which the queries that follow are read with; in a directive the
+    // parser has already applied them to the rest of the program. discontiguous/1 only checks the predicate
+    // indicators it declares.
+    // Outside a directive initialization/1 simply calls its goal.
+    fn step_declaration(&mut self, cp: ChoicePoint) -> Result<(), PrologError> {
+        let goal = &cp.goal().atom;
+        let args: Vec<Term> = goal.args.iter().map(|arg| apply_subs(arg, &cp.subs)).collect();
+        let mut first = vec![];
+        match goal.name.as_str() {
+            "op" => self.db.op(&args[0], &args[1], &args[2])?,
+            "dynamic" => {
+                for (name, arity) in predicate_indicators(&args[0])? {
+                    check_not_builtin(&name, arity)?;
+                    self.db.declare_dynamic(&name, arity)?;
+                }
+            }
+            "discontiguous" => {
+                predicate_indicators(&args[0])?;
+            }
+            "set_prolog_flag" => match (&args[0], &args[1]) {
+                (Term::Variable(_), _) | (_, Term::Variable(_)) => return Err(PrologError::Instantiation),
+                (Term::Constant(flag), Term::Constant(value)) if flag == "double_quotes" => match DoubleQuotes::from_name(value) {
+                    Some(double_quotes) => self.db.set_double_quotes(double_quotes),
+                    None => return Err(PrologError::domain_error("flag_value", format!("{}+{}", flag, value))),
+                },
+                (Term::Constant(flag), value) if flag == "double_quotes" => {
+                    return Err(PrologError::domain_error("flag_value", format!("{}+{}", flag, format_term(value))));
+                }
+                (Term::Constant(flag), _) => return Err(PrologError::domain_error("prolog_flag", flag)),
+                (flag, _) => return Err(PrologError::type_error("atom", format_term(flag))),
+            },
+            "ensure_loaded" | "use_module" => match &args[0] {
+                Term::Variable(_) => return Err(PrologError::Instantiation),
+                // the libraries are always loaded
+                source @ Term::Compound { name, args } if name == "library" && args.len() == 1 => match &args[0] {
+                    Term::Variable(_) => return Err(PrologError::Instantiation),
+                    Term::Constant(library) if library::is_library(library) => {}
+                    _ => return Err(PrologError::existence_error("source_sink", format_term(source))),
+                },
+                source => return Err(PrologError::existence_error("source_sink", format_term(source))),
+            },
+            _ => match &args[0] {
+                Term::Variable(_) => return Err(PrologError::Instantiation),
+                term => first.push(Atom::from_term(term).ok_or_else(|| PrologError::type_error("callable", format_term(term)))?),
+            },
+        }
+        let node = self.trace_step(&cp.node, goal, &cp.subs);
+        self.push_resolvent(&cp, first, cp.subs.clone(), node);
+        Ok(())
+    }
+
+    fn fresh_var(&mut self) -> Term {
+        self.counter += 1;
+        Term::Variable(fresh_name("_", self.counter))
+    }
+
+    // Replaces the first goal of `cp` by `first` and pushes the new resolvent. The new goals come from
+    // the body of the same clause, so they share the cut barrier of the goal they replace.
+    fn push_resolvent(&mut self, cp: &ChoicePoint, first: Vec<Atom>, subs: Substitution, node: Vec<usize>) {
+        let cut_barrier = cp.goal().cut_barrier;
+        let goals = Goals::prepend(first.into_iter().map(|atom| Goal { atom, cut_barrier }), cp.goals.rest());
+        self.stack.push(ChoicePoint { goals, subs, clause: None, node });
+    }
+
+    // Executes true, fail, cut, negation, conjunction, disjunction, if-then(-else) and ^
+    fn step_control(&mut self, cp: ChoicePoint) -> Result<(), PrologError> {
+        let goal = &cp.goal().atom;
+        // the goal argument `i`, keeping where it was read
+        let callable = |i: usize| {
+            let atom = body_goal(&goal.args[i], &cp.subs)?;
+            Ok::<_, PrologError>(match goal.arg_spans.get(i) {
+                Some(spans) => atom.with_spans(spans),
+                None => atom,
+            })
+        };
+
+        match goal.name.as_str() {
+            "true" => {
+                let node = self.trace_step(&cp.node, goal, &cp.subs);
+                self.push_resolvent(&cp, vec![], cp.subs.clone(), node);
+            }
+            "fail" | "false" => {}
+            "!" => {
+                self.stack.truncate(cp.goal().cut_barrier);
+                let node = self.trace_step(&cp.node, goal, &cp.subs);
+                self.push_resolvent(&cp, vec![], cp.subs.clone(), node);
+            }
+            // Negation as failure: succeeds without binding anything iff the goal has no solution
+            "\\+" | "not" => {
+                let inner = callable(0)?;
+                if self.solve_once(inner, &cp.subs)?.is_some() {
+                    return Ok(());
+                }
+                let node = self.trace_step(&cp.node, goal, &cp.subs);
+                self.push_resolvent(&cp, vec![], cp.subs.clone(), node);
+            }
+            "," => {
+                let (left, right) = (callable(0)?, callable(1)?);
+                self.push_resolvent(&cp, vec![left, right], cp.subs.clone(), cp.node.clone());
+            }
+            ";" => {
+                let left = callable(0)?;
+                if left.name == "->" && left.args.len() == 2 {
+                    let (condition, then) = (&left.args[0], &left.args[1]);
+                    return self.if_then_else(&cp, condition, then, Some(&goal.args[1]));
+                }
+                // The right branch is an alternative of the disjunction; a cut in either branch is
+                // transparent and cuts the enclosing clause
+                let right = callable(1)?;
+                self.push_resolvent(&cp, vec![right], cp.subs.clone(), cp.node.clone());
+                self.push_resolvent(&cp, vec![left], cp.subs.clone(), cp.node.clone());
+            }
+            "->" => return self.if_then_else(&cp, &goal.args[0], &goal.args[1], None),
+            // V^Goal marks V as existential for bagof/3 and setof/3; called on its own it calls Goal
+            "^" => {
+                let inner = callable(1)?;
+                self.push_resolvent(&cp, vec![inner], cp.subs.clone(), cp.node.clone());
+            }
+            _ => unreachable!("not a control construct: {}", goal.name),
+        }
+        Ok(())
+    }
+
+    // (Condition -> Then ; Else): the condition is proven in isolation and committed to its first solution.
+    // Without an else branch the construct fails when the condition does.
+    fn if_then_else(&mut self, cp: &ChoicePoint, condition: &Term, then: &Term, otherwise: Option<&Term>) -> Result<(), PrologError> {
+        let condition = body_goal(condition, &cp.subs)?;
+        match self.solve_once(condition, &cp.subs)? {
+            Some(subs) => {
+                let then = body_goal(then, &subs)?;
+                self.push_resolvent(cp, vec![then], subs, cp.node.clone());
+            }
+            None => {
+                if let Some(otherwise) = otherwise {
+                    let otherwise = body_goal(otherwise, &cp.subs)?;
+                    self.push_resolvent(cp, vec![otherwise], cp.subs.clone(), cp.node.clone());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // call(Goal, Extra...): calls Goal with the extra arguments added after its own. The call is opaque
+    // to cut: a cut inside Goal only removes the choicepoints created since the call.
+    fn step_call(&mut self, cp: ChoicePoint) -> Result<(), PrologError> {
+        let goal = &cp.goal().atom;
+        let extra = goal.args[1..].iter().cloned();
+        let callee = match apply_subs(&goal.args[0], &cp.subs) {
+            Term::Variable(_) => return Err(PrologError::Instantiation),
+            Term::Constant(name) => Atom::new(name, extra.collect()),
+            Term::Compound { name, args } => Atom::new(name, args.into_iter().chain(extra).collect()),
+            term => return Err(PrologError::type_error("callable", format_term(&term))),
+        };
+        let callee = Atom { span: goal.span, ..callee };
+        let goals = Goals::prepend(std::iter::once(Goal { atom: callee, cut_barrier: self.stack.len() }), cp.goals.rest());
+        self.stack.push(ChoicePoint { goals, clause: None, ..cp });
+        Ok(())
+    }
+
+    // Tries the clauses of the database against the first goal of `cp`, from the cursor in `cp.clause` on.
+    // On success the remaining alternatives and the new resolvent are pushed onto the stack.
+    // Calling a predicate that has no clauses at all and is not dynamic is an existence error rather than a failure.
+    fn step_clauses(&mut self, cp: ChoicePoint) -> Result<(), PrologError> {
+        let goal = &cp.goal().atom;
+        // Height of the stack below this call: the alternatives pushed from here on belong to it
+        let call_height = self.stack.len();
+
+        let cursor = match &cp.clause {
+            Some(cursor) => cursor.clone(),
+            None if !self.db.is_defined(&goal.name, goal.args.len()) => {
+                return Err(PrologError::existence_error("procedure", format!("{}/{}", goal.name, goal.args.len())));
+            }
+            None => self.db.start(goal),
+        };
+        let mut candidate = self.db.next_clause(goal, &cp.subs, &cursor);
+        while let Some((position, clause)) = candidate {
+            let next = cursor.after(position);
+            candidate = self.db.next_clause(goal, &cp.subs, &next);
+            // Facts are renamed too, since variables in a fact are as local to it as those in a rule
+            let (head, body) = fresh_rule(&clause.head, &clause.body, &mut self.counter);
+
+            let mut new_subs = cp.subs.clone();
+            if !unify_atoms_with(goal, &head, &mut new_subs) {
+                continue;
+            }
+
+            let node = self.trace_step(&cp.node, &head, &new_subs);
+            let new_goals = Goals::prepend(body.into_iter().map(|atom| Goal { atom, cut_barrier: call_height }), cp.goals.rest());
+
+            if candidate.is_some() {
+                self.stack.push(ChoicePoint { clause: Some(next), ..cp });
+            }
+            self.stack.push(ChoicePoint { goals: new_goals, subs: new_subs, clause: None, node });
+            return Ok(());
+        }
+        Ok(())
+    }
+
+    // assert/1, asserta/1 and assertz/1 add a clause to the database; assert/1 adds it at the end
+    fn step_assert(&mut self, cp: ChoicePoint) -> Result<(), PrologError> {
+        let goal = &cp.goal().atom;
+        let (head, body) = clause_parts(&apply_subs(&goal.args[0], &cp.subs))?;
+        let body = body_goals(&body)?;
+        self.db.assert(head, body, goal.name == "asserta")?;
+        let node = self.trace_step(&cp.node, goal, &cp.subs);
+        self.push_resolvent(&cp, vec![], cp.subs.clone(), node);
+        Ok(())
+    }
+
+    // retract(Clause) removes the first clause of the database that unifies with Clause, and the next
+    // matching ones on backtracking. Like a call, it only sees the clauses that existed when it started.
+    fn step_retract(&mut self, cp: ChoicePoint) -> Result<(), PrologError> {
+        let goal = &cp.goal().atom;
+        let (pattern, body_pattern) = clause_parts(&apply_subs(&goal.args[0], &cp.subs))?;
+        self.db.check_modifiable(&pattern.name, pattern.args.len())?;
+
+        let cursor = cp.clause.clone().unwrap_or_else(|| self.db.start(&pattern));
+        let mut candidate = self.db.next_clause(&pattern, &cp.subs, &cursor);
+        while let Some((position, clause)) = candidate {
+            let next = cursor.after(position);
+            candidate = self.db.next_clause(&pattern, &cp.subs, &next);
+            let (head, body) = fresh_rule(&clause.head, &clause.body, &mut self.counter);
+
+            let mut subs = cp.subs.clone();
+            if !unify_atoms_with(&head, &pattern, &mut subs) || !unify_terms(&body_term(body), &body_pattern, &mut subs) {
+                continue;
+            }
+            self.db.retract(&pattern.name, pattern.args.len(), position);
+            if candidate.is_some() {
+                self.stack.push(ChoicePoint { clause: Some(next), ..cp.clone() });
+            }
+            let node = self.trace_step(&cp.node, goal, &subs);
+            self.push_resolvent(&cp, vec![], subs, node);
+            return Ok(());
+        }
+        Ok(())
+    }
+
+    // retractall(Head) removes every clause whose head unifies with Head and always succeeds.
+    // An unknown predicate becomes dynamic.
+    fn step_retractall(&mut self, cp: ChoicePoint) -> Result<(), PrologError> {
+        let goal = &cp.goal().atom;
+        let (pattern, _) = clause_parts(&apply_subs(&goal.args[0], &cp.subs))?;
+        let (name, arity) = (pattern.name.as_str(), pattern.args.len());
+        self.db.check_modifiable(name, arity)?;
+        if !self.db.is_defined(name, arity) {
+            self.db.declare_dynamic(name, arity)?;
+        }
+
+        let mut cursor = self.db.start(&pattern);
+        while let Some((position, clause)) = self.db.next_clause(&pattern, &cp.subs, &cursor) {
+            let (head, _) = fresh_rule(&clause.head, &[], &mut self.counter);
+            if unify_atoms_with(&pattern, &head, &mut cp.subs.clone()) {
+                self.db.retract(name, arity, position);
+            }
+            cursor = cursor.after(position);
+        }
+        let node = self.trace_step(&cp.node, goal, &cp.subs);
+        self.push_resolvent(&cp, vec![], cp.subs.clone(), node);
+        Ok(())
+    }
+
+    // abolish(Name/Arity) removes all clauses of a dynamic predicate and its dynamic declaration
+    fn step_abolish(&mut self, cp: ChoicePoint) -> Result<(), PrologError> {
+        let goal = &cp.goal().atom;
+        let (name, arity) = predicate_indicator(&apply_subs(&goal.args[0], &cp.subs))?;
+        check_not_builtin(&name, arity)?;
+        self.db.abolish(&name, arity)?;
+        let node = self.trace_step(&cp.node, goal, &cp.subs);
+        self.push_resolvent(&cp, vec![], cp.subs.clone(), node);
+        Ok(())
+    }
+}
+
+impl Iterator for Solutions {
+    type Item = Result<Substitution, PrologError>;
+
+    fn next(&mut self) -> Option<Result<Substitution, PrologError>> {
+        let subs = match self.next_subs()? {
+            Ok(subs) => subs,
+            Err(e) => return Some(Err(e)),
+        };
+        let mut answer = Substitution::new();
+        for var in &self.query_vars {
+            answer.insert(var.clone(), apply_subs(&Term::Variable(var.clone()), &subs));
+        }
+        Some(Ok(answer))
+    }
+}
+
+// Builds the full resolution tree of a query. Only terminates when the query has finitely many answers;
+// use `solve` to enumerate answers lazily.
+pub fn resolve_query(query: &[Atom], db: &[Statement]) -> ResolutionNode {
+    let mut solutions = solve(query, db).traced();
+    for _ in solutions.by_ref() {}
+    solutions.into_tree().unwrap_or(ResolutionNode { goal: None, subs: Substitution::new(), children: vec![] })
+}
+
+/// Loads a program: its clauses make up the database and each directive is run when it is reached,
+/// against the clauses that come before it. `initialization(Goal)` runs once the whole program is loaded.
+/// A directive that fails or raises an error does not stop the load but is returned as a warning, and so does
+/// a clause for a builtin predicate, which is left out since the builtin would always be called instead.
+pub fn consult(program: Vec<Statement>, source: Option<&str>) -> (ClauseStore, Vec<SourceError>) {
+    let db = ClauseStore::default();
+    let mut warnings = vec![];
+    let mut initialization = vec![];
+    for stmt in program {
+        match &stmt {
+            Statement::Directive { body } => match body.as_slice() {
+                [goal] if goal.name == "initialization" && goal.args.len() == 1 => initialization.push(stmt),
+                _ => run_directive(&stmt, &db, source, &mut warnings),
+            },
+            Statement::Fact(head) | Statement::Rule { head, .. } => match check_not_builtin(&head.name, head.args.len()) {
+                Ok(()) => db.add(stmt),
+                Err(e) => warnings.push(SourceError::new(e.to_string(), stmt.span(), source)),
+            },
+            Statement::Query { .. } => {}
+        }
+    }
+    for stmt in &initialization {
+        run_directive(stmt, &db, source, &mut warnings);
+    }
+    (db, warnings)
+}
+
+// Proves a directive once, keeping a warning if it has no solution or raises an error
+fn run_directive(directive: &Statement, db: &ClauseStore, source: Option<&str>, warnings: &mut Vec<SourceError>) {
+    let Statement::Directive { body } = directive else { return };
+    let goals: Vec<String> = body.iter().map(|goal| format_term(&Term::from(goal.clone()))).collect();
+    let span = directive.span();
+    let mut solutions = solve(body, db.clone());
+    let (message, span) = match solutions.next() {
+        Some(Ok(_)) => return,
+        // an error points at the term it is about, if that was read as part of the directive
+        Some(Err(e)) => {
+            let culprit = solutions.error_span(&e).filter(|culprit| span.start <= culprit.start && culprit.end <= span.end);
+            (format!("Goal (directive) {} raised: {}", goals.join(", "), e), culprit.unwrap_or(span))
+        }
+        None => (format!("Goal (directive) failed: {}", goals.join(", ")), span),
+    };
+    warnings.push(SourceError::new(message, span, source));
+}
+
+// The predicate indicators Name/Arity of a conjunction or list of them, as given to dynamic/1
+fn predicate_indicators(term: &Term) -> Result<Vec<(String, usize)>, PrologError> {
+    match term {
+        Term::Compound { name, args } if (name == "," || name == ".") && args.len() == 2 => {
+            let mut indicators = predicate_indicators(&args[0])?;
+            if args[1] != Term::nil() {
+                indicators.extend(predicate_indicators(&args[1])?);
+            }
+            Ok(indicators)
+        }
+        _ => Ok(vec![predicate_indicator(term)?]),
+    }
+}
+
+// The name and arity of a predicate indicator Name/Arity
+fn predicate_indicator(term: &Term) -> Result<(String, usize), PrologError> {
+    match term {
+        Term::Variable(_) => Err(PrologError::Instantiation),
+        Term::Compound { name, args } if name == "/" && args.len() == 2 => match (&args[0], &args[1]) {
+            (Term::Variable(_), _) | (_, Term::Variable(_)) => Err(PrologError::Instantiation),
+            (Term::Constant(name), Term::Number(Number::Integer(arity))) if *arity >= 0 => Ok((name.clone(), *arity as usize)),
+            (Term::Constant(_), arity @ Term::Number(Number::Integer(_))) => {
+                Err(PrologError::domain_error("not_less_than_zero", format_term(arity)))
+            }
+            (Term::Constant(_), arity) => Err(PrologError::type_error("integer", format_term(arity))),
+            (name, _) => Err(PrologError::type_error("atom", format_term(name))),
+        },
+        _ => Err(PrologError::type_error("predicate_indicator", format_term(term))),
+    }
+}
+
+// Splits a clause term Head :- Body, or a fact Head, into its head and body terms
+fn clause_parts(clause: &Term) -> Result<(Atom, Term), PrologError> {
+    let (head, body) = match clause {
+        Term::Compound { name, args } if name == ":-" && args.len() == 2 => (&args[0], args[1].clone()),
+        _ => (clause, Term::Constant("true".to_string())),
+    };
+    match head {
+        Term::Variable(_) => Err(PrologError::Instantiation),
+        head => {
+            let head = Atom::from_term(head).ok_or_else(|| PrologError::type_error("callable", format_term(head)))?;
+            check_not_builtin(&head.name, head.args.len())?;
+            Ok((head, body))
+        }
+    }
+}
+
+// The goals of a clause body term, as they are stored in the database: a variable X is stored as call(X)
+fn body_goals(body: &Term) -> Result<Vec<Atom>, PrologError> {
+    match body {
+        Term::Compound { name, args } if name == "," && args.len() == 2 => {
+            let mut goals = body_goals(&args[0])?;
+            goals.extend(body_goals(&args[1])?);
+            Ok(goals)
+        }
+        Term::Variable(_) => Ok(vec![Atom::new("call", vec![body.clone()])]),
+        goal => Atom::from_term(goal).map(|atom| vec![atom]).ok_or_else(|| PrologError::type_error("callable", format_term(body))),
+    }
+}
+
+// The body of a stored clause as a term, `true` for a fact
+fn body_term(body: Vec<Atom>) -> Term {
+    let mut goals = body.into_iter().rev().map(Term::from);
+    let last = goals.next().unwrap_or_else(|| Term::Constant("true".to_string()));
+    goals.fold(last, |right, left| Term::Compound { name: ",".to_string(), args: vec![left, right] })
+}
+
+// Builtin predicates and control constructs cannot be declared, asserted or retracted
+fn check_not_builtin(name: &str, arity: usize) -> Result<(), PrologError> {
+    match builtin(name, arity) {
+        Some(_) => Err(PrologError::permission_error("modify", "static_procedure", format!("{}/{}", name, arity))),
+        None => Ok(()),
+    }
 }
 
 pub fn fresh_rule(head: &Atom, body: &[Atom], counter: &mut usize) -> (Atom, Vec<Atom>) {
@@ -78,14 +1184,15 @@ pub fn fresh_rule(head: &Atom, body: &[Atom], counter: &mut usize) -> (Atom, Vec
 
     fn freshen_term(term: &Term, counter: &mut usize, var_map: &mut HashMap<String, String>) -> Term {
         match term {
+            Term::Variable(v) if v == "_" => anonymous_var(counter),
             Term::Variable(v) => {
                 let name = var_map.entry(v.clone()).or_insert_with(|| {
                     *counter += 1;
-                    format!("{}_{}", v, counter)
+                    fresh_name(v, *counter)
                 });
                 Term::Variable(name.clone())
             }
-            Term::Constant(c) => Term::Constant(c.clone()),
+            Term::Constant(_) | Term::Number(_) | Term::String(_) => term.clone(),
             Term::Compound { name, args } => Term::Compound {
                 name: name.clone(),
                 args: args.iter().map(|t| freshen_term(t, counter, var_map)).collect(),
@@ -96,6 +1203,8 @@ pub fn fresh_rule(head: &Atom, body: &[Atom], counter: &mut usize) -> (Atom, Vec
     let fresh_head = Atom {
         name: head.name.clone(),
         args: head.args.iter().map(|t| freshen_term(t, counter, &mut var_map)).collect(),
+        span: head.span,
+        arg_spans: head.arg_spans.clone(),
     };
 
     let fresh_body: Vec<Atom> = body
@@ -103,6 +1212,8 @@ pub fn fresh_rule(head: &Atom, body: &[Atom], counter: &mut usize) -> (Atom, Vec
         .map(|atom| Atom {
             name: atom.name.clone(),
             args: atom.args.iter().map(|t| freshen_term(t, counter, &mut var_map)).collect(),
+            span: atom.span,
+            arg_spans: atom.arg_spans.clone(),
         })
         .collect();
 
@@ -110,30 +1221,88 @@ pub fn fresh_rule(head: &Atom, body: &[Atom], counter: &mut usize) -> (Atom, Vec
 }
 
 
-// Backwards substitution
-pub fn extract_query_results(tree: &ResolutionNode, query_vars: &[String]) -> Vec<Substitution> {
-    fn merge_subs(parent: &Substitution, child: &Substitution) -> Substitution { // helper
-        let mut merged = parent.clone();
-        for (k, v) in child {
-            merged.insert(k.clone(), v.clone());
-        }
-        merged
+// The goal a term stands for as part of a control construct. A variable is called through call/1 when it is
+// reached, since goals to its left may still bind it; other terms that are not callable are a type error.
+fn body_goal(term: &Term, subs: &Substitution) -> Result<Atom, PrologError> {
+    match apply_subs(term, subs) {
+        var @ Term::Variable(_) => Ok(Atom::new("call", vec![var])),
+        term => Atom::from_term(&term).ok_or_else(|| PrologError::type_error("callable", format_term(&term))),
     }
-    fn apply_subs(term: &Term, subs: &Substitution) -> Term { // helper
+}
+
+// The variables of a term in the order they first appear
+fn term_variables(term: &Term) -> Vec<String> {
+    fn collect(term: &Term, vars: &mut Vec<String>) {
         match term {
-            Term::Variable(v) => {
-                if let Some(t) = subs.get(v) {
-                    apply_subs(t, subs)
-                } else {
-                    term.clone()
+            Term::Variable(v) if !vars.contains(v) => vars.push(v.clone()),
+            Term::Compound { args, .. } => args.iter().for_each(|arg| collect(arg, vars)),
+            _ => {}
+        }
+    }
+    let mut vars = vec![];
+    collect(term, &mut vars);
+    vars
+}
+
+// True if the terms are equal up to a consistent renaming of their variables
+fn is_variant(t1: &Term, t2: &Term) -> bool {
+    fn walk<'a>(t1: &'a Term, t2: &'a Term, renaming: &mut HashMap<&'a str, &'a str>, used: &mut HashSet<&'a str>) -> bool {
+        match (t1, t2) {
+            (Term::Variable(v1), Term::Variable(v2)) => match renaming.get(v1.as_str()) {
+                Some(mapped) => mapped == v2,
+                None => {
+                    renaming.insert(v1, v2);
+                    used.insert(v2)
                 }
+            },
+            (Term::Compound { name: n1, args: a1 }, Term::Compound { name: n2, args: a2 }) => {
+                n1 == n2 && a1.len() == a2.len() && a1.iter().zip(a2).all(|(x, y)| walk(x, y, renaming, used))
             }
-            Term::Constant(_) => term.clone(),
+            (Term::Variable(_), _) | (_, Term::Variable(_)) => false,
+            _ => t1 == t2,
+        }
+    }
+    walk(t1, t2, &mut HashMap::new(), &mut HashSet::new())
+}
+
+// The list argument of findall/3 and similar must be a list or a partial list
+fn check_partial_list(list: &Term) -> Result<(), PrologError> {
+    match list.list_items().1 {
+        Term::Variable(_) => Ok(()),
+        tail if *tail == Term::nil() => Ok(()),
+        _ => Err(PrologError::type_error("list", format_term(list))),
+    }
+}
+
+// Every occurrence of the anonymous variable `_` is a new variable
+fn anonymous_var(counter: &mut usize) -> Term {
+    *counter += 1;
+    Term::Variable(fresh_name("_", *counter))
+}
+
+// Gives each `_` of a query goal its own variable; the named variables of a query are kept
+fn rename_anonymous(atom: &Atom, counter: &mut usize) -> Atom {
+    fn rename(term: &Term, counter: &mut usize) -> Term {
+        match term {
+            Term::Variable(v) if v == "_" => anonymous_var(counter),
             Term::Compound { name, args } => Term::Compound {
                 name: name.clone(),
-                args: args.iter().map(|t| apply_subs(t, subs)).collect(),
+                args: args.iter().map(|t| rename(t, counter)).collect(),
             },
+            _ => term.clone(),
+        }
+    }
+    Atom { args: atom.args.iter().map(|t| rename(t, counter)).collect(), ..atom.clone() }
+}
+
+// Backwards substitution
+pub fn extract_query_results(tree: &ResolutionNode, query_vars: &[String]) -> Vec<Substitution> {
+    fn merge_subs(parent: &Substitution, child: &Substitution) -> Substitution { // helper
+        let mut merged = parent.clone();
+        for (k, v) in child {
+            merged.insert(k.clone(), v.clone());
         }
+        merged
     }
 
     if tree.children.is_empty() {
@@ -149,7 +1318,7 @@ pub fn extract_query_results(tree: &ResolutionNode, query_vars: &[String]) -> Ve
     let mut results = vec![];
     for child in &tree.children {
         let merged = merge_subs(&tree.subs, &child.subs);
-        let mut child_node = ResolutionNode {
+        let child_node = ResolutionNode {
             goal: child.goal.clone(),
             subs: merged,
             children: child.children.clone(),
@@ -161,6 +1330,23 @@ pub fn extract_query_results(tree: &ResolutionNode, query_vars: &[String]) -> Ve
 }
 
 // Helpers
+pub fn apply_subs(term: &Term, subs: &Substitution) -> Term {
+    match term {
+        Term::Variable(v) => {
+            if let Some(t) = subs.get(v) {
+                apply_subs(t, subs)
+            } else {
+                term.clone()
+            }
+        }
+        Term::Constant(_) | Term::Number(_) | Term::String(_) => term.clone(),
+        Term::Compound { name, args } => Term::Compound {
+            name: name.clone(),
+            args: args.iter().map(|t| apply_subs(t, subs)).collect(),
+        },
+    }
+}
+
 pub fn get_query_vars(query: &[Atom]) -> Vec<String> {
     let mut vars_set = HashSet::new();
     for atom in query {
@@ -168,6 +1354,8 @@ pub fn get_query_vars(query: &[Atom]) -> Vec<String> {
             get_term_vars(term, &mut vars_set);
         }
     }
+    // anonymous variables are never part of an answer
+    vars_set.remove("_");
     let mut vars: Vec<String> = vars_set.into_iter().collect();
     vars.sort(); // optional, to have deterministic order
     vars
@@ -178,7 +1366,7 @@ pub fn get_term_vars(term: &Term, vars: &mut HashSet<String>) {
         Term::Variable(v) => {
             vars.insert(v.clone());
         }
-        Term::Constant(_) => {}
+        Term::Constant(_) | Term::Number(_) | Term::String(_) => {}
         Term::Compound { args, .. } => {
             for t in args {
                 get_term_vars(t, vars);
@@ -190,8 +1378,22 @@ pub fn get_term_vars(term: &Term, vars: &mut HashSet<String>) {
 // Tests
 #[cfg(test)]
 mod tests {
-    use crate::unificator::print_substitution;
+    use crate::unificator::{format_answer, print_substitution};
+    use crate::operators::OpType;
+    use crate::parser::{parse_query, Parser};
+    use crate::tokenizer::{tokenize, Number};
     use super::*;
+    use std::rc::Rc;
+
+    fn program(src: &str) -> Rc<[Statement]> { // helper
+        let (stmts, errors) = Parser::new(tokenize(src).unwrap()).parse_program();
+        assert_eq!(errors, vec![]);
+        stmts.into()
+    }
+
+    fn query(src: &str) -> Vec<Atom> { // helper
+        parse_query(tokenize(src).unwrap()).unwrap()
+    }
 
     fn print_tree(node: &ResolutionNode, depth: usize) { // helper
         let indent = "  ".repeat(depth);
@@ -203,54 +1405,52 @@ mod tests {
 
     #[test]
     fn test_fresh_rule_simple() {
-        let head = Atom {
-            name: "p".to_string(),
-            args: vec![Term::Variable("X".to_string())],
-        };
+        let head = Atom::new("p", vec![Term::Variable("X".to_string())]);
         let body = vec![
-            Atom {
-                name: "q".to_string(),
-                args: vec![Term::Variable("X".to_string())],
-            }
+            Atom::new("q", vec![Term::Variable("X".to_string())])
         ];
         let mut counter = 0;
         let (fresh_head, fresh_body) = fresh_rule(&head, &body, &mut counter);
 
-        assert_eq!(fresh_head.args[0], Term::Variable("X_1".to_string()));
-        assert_eq!(fresh_body[0].args[0], Term::Variable("X_1".to_string()));
+        assert_eq!(fresh_head.args[0], Term::Variable("X#1".to_string()));
+        assert_eq!(fresh_body[0].args[0], Term::Variable("X#1".to_string()));
         assert_eq!(counter, 1);
     }
 
     #[test]
     fn test_fresh_rule_multiple_vars() {
-        let head = Atom {
-            name: "p".to_string(),
-            args: vec![Term::Variable("X".to_string()), Term::Variable("Y".to_string())],
-        };
+        let head = Atom::new("p", vec![Term::Variable("X".to_string()), Term::Variable("Y".to_string())]);
         let body = vec![
-            Atom {
-                name: "q".to_string(),
-                args: vec![Term::Variable("X".to_string()), Term::Variable("Y".to_string())],
-            }
+            Atom::new("q", vec![Term::Variable("X".to_string()), Term::Variable("Y".to_string())])
         ];
         let mut counter = 0;
         let (fresh_head, fresh_body) = fresh_rule(&head, &body, &mut counter);
 
-        assert_eq!(fresh_head.args[0], Term::Variable("X_1".to_string()));
-        assert_eq!(fresh_head.args[1], Term::Variable("Y_2".to_string()));
-        assert_eq!(fresh_body[0].args[0], Term::Variable("X_1".to_string()));
-        assert_eq!(fresh_body[0].args[1], Term::Variable("Y_2".to_string()));
+        assert_eq!(fresh_head.args[0], Term::Variable("X#1".to_string()));
+        assert_eq!(fresh_head.args[1], Term::Variable("Y#2".to_string()));
+        assert_eq!(fresh_body[0].args[0], Term::Variable("X#1".to_string()));
+        assert_eq!(fresh_body[0].args[1], Term::Variable("Y#2".to_string()));
         assert_eq!(counter, 2);
     }
 
+    #[test]
+    fn test_fresh_rule_anonymous_vars() {
+        let head = Atom::new("p", vec![Term::Variable("_".to_string()), Term::Variable("_".to_string())]);
+        let body = vec![Atom::new("q", vec![Term::Variable("_X".to_string()), Term::Variable("_X".to_string())])];
+        let mut counter = 0;
+        let (fresh_head, fresh_body) = fresh_rule(&head, &body, &mut counter);
+
+        // each '_' is a new variable, while '_X' is an ordinary named variable
+        assert_ne!(fresh_head.args[0], fresh_head.args[1]);
+        assert_eq!(fresh_body[0].args[0], fresh_body[0].args[1]);
+        assert_eq!(counter, 3);
+    }
+
     #[test]
     fn test_extract_query_results_leaf_node() {
         let query_vars = vec!["X".to_string()];
         let leaf = ResolutionNode {
-            goal: Option::from(Atom {
-                name: "p".to_string(),
-                args: vec![Term::Variable("X".to_string())],
-            }),
+            goal: Option::from(Atom::new("p", vec![Term::Variable("X".to_string())])),
             subs: {
                 let mut s = Substitution::new();
                 s.insert("X".to_string(), Term::Constant("a".to_string()));
@@ -267,10 +1467,7 @@ mod tests {
     fn test_extract_query_results_with_child() {
         let query_vars = vec!["X".to_string()];
         let child = ResolutionNode {
-            goal: Option::from(Atom {
-                name: "q".to_string(),
-                args: vec![Term::Variable("X".to_string())],
-            }),
+            goal: Option::from(Atom::new("q", vec![Term::Variable("X".to_string())])),
             subs: {
                 let mut s = Substitution::new();
                 s.insert("X".to_string(), Term::Constant("b".to_string()));
@@ -279,10 +1476,7 @@ mod tests {
             children: vec![],
         };
         let parent = ResolutionNode {
-            goal: Option::from(Atom {
-                name: "p".to_string(),
-                args: vec![Term::Variable("X".to_string())],
-            }),
+            goal: Option::from(Atom::new("p", vec![Term::Variable("X".to_string())])),
             subs: Substitution::new(),
             children: vec![child],
         };
@@ -294,14 +1488,8 @@ mod tests {
     #[test]
     fn test_get_query_vars() {
         let query = vec![
-            Atom {
-                name: "p".to_string(),
-                args: vec![Term::Variable("X".to_string()), Term::Constant("a".to_string())],
-            },
-            Atom {
-                name: "q".to_string(),
-                args: vec![Term::Variable("Y".to_string())],
-            },
+            Atom::new("p", vec![Term::Variable("X".to_string()), Term::Constant("a".to_string())]),
+            Atom::new("q", vec![Term::Variable("Y".to_string())]),
         ];
         let vars = get_query_vars(&query);
         assert_eq!(vars, vec!["X".to_string(), "Y".to_string()]);
@@ -329,45 +1517,45 @@ mod tests {
     #[test]
     fn test_resolution_tree() {
         let db = vec![
-            Statement::Fact(Atom {
-                name: "parent".to_string(),
-                args: vec![Term::Constant("john".to_string()), Term::Constant("mary".to_string())],
-            }),
-            Statement::Fact(Atom {
-                name: "parent".to_string(),
-                args: vec![Term::Constant("mary".to_string()), Term::Constant("pesho".to_string())],
-            }),
+            Statement::Fact(Atom::new(
+                "parent",
+                vec![Term::Constant("john".to_string()), Term::Constant("mary".to_string())],
+            )),
+            Statement::Fact(Atom::new(
+                "parent",
+                vec![Term::Constant("mary".to_string()), Term::Constant("pesho".to_string())],
+            )),
             Statement::Rule {
-                head: Atom {
-                    name: "grandparent".to_string(),
-                    args: vec![Term::Variable("X".to_string()), Term::Variable("Y".to_string())],
-                },
+                head: Atom::new(
+                    "grandparent",
+                    vec![Term::Variable("X".to_string()), Term::Variable("Y".to_string())],
+                ),
                 body: vec![
-                    Atom {
-                        name: "parent".to_string(),
-                        args: vec![Term::Variable("X".to_string()), Term::Variable("Z".to_string())],
-                    },
-                    Atom {
-                        name: "parent".to_string(),
-                        args: vec![Term::Variable("Z".to_string()), Term::Variable("Y".to_string())],
-                    },
+                    Atom::new(
+                        "parent",
+                        vec![Term::Variable("X".to_string()), Term::Variable("Z".to_string())],
+                    ),
+                    Atom::new(
+                        "parent",
+                        vec![Term::Variable("Z".to_string()), Term::Variable("Y".to_string())],
+                    ),
                 ],
             },
         ];
 
         // ?- grandparent(john, Y).
         let query = vec![
-            Atom {
-                name: "grandparent".to_string(),
-                args: vec![Term::Constant("john".to_string()), Term::Variable("Y".to_string())],
-            }
+            Atom::new(
+                "grandparent",
+                vec![Term::Constant("john".to_string()), Term::Variable("Y".to_string())],
+            )
         ];
 
         let tree = resolve_query(&query, &db);
 
         print_tree(&tree, 0);
 
-        let query_vars = get_query_vars(&*query); //vec!["Y".to_string()];
+        let query_vars = get_query_vars(&query); //vec!["Y".to_string()];
         let results = extract_query_results(&tree, &query_vars);
 
         for s in results {
@@ -375,4 +1563,734 @@ mod tests {
             //println!("{:?}", s);
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_solve_first_answers_of_infinite_relation() {
+        let db = program("nat(zero). nat(s(X)) :- nat(X).");
+        let q = query("?- nat(N).");
+        let answers: Vec<Substitution> = solve(&q, db).take(3).map(Result::unwrap).collect();
+
+        assert_eq!(answers.len(), 3);
+        assert_eq!(answers[0].get("N").unwrap(), &Term::Constant("zero".to_string()));
+        assert_eq!(answers[2].get("N").unwrap(), &Term::Compound {
+            name: "s".to_string(),
+            args: vec![Term::Compound {
+                name: "s".to_string(),
+                args: vec![Term::Constant("zero".to_string())],
+            }],
+        });
+    }
+
+    #[test]
+    fn test_solve_order_and_exhaustion() {
+        let db = program("
+            parent(john, mary). parent(john, ann). parent(mary, pesho).
+            grandparent(X, Y) :- parent(X, Z), parent(Z, Y).
+        ");
+        let children: Vec<Term> = solve(&query("?- parent(john, C)."), db.clone())
+            .map(|s| s.unwrap().get("C").unwrap().clone())
+            .collect();
+        assert_eq!(children, vec![Term::Constant("mary".to_string()), Term::Constant("ann".to_string())]);
+
+        let mut grandchildren = solve(&query("?- grandparent(john, Y)."), db.clone());
+        assert_eq!(grandchildren.next().unwrap().unwrap().get("Y").unwrap(), &Term::Constant("pesho".to_string()));
+        assert!(grandchildren.next().is_none());
+        assert!(grandchildren.is_exhausted());
+        assert!(solve(&query("?- parent(pesho, X)."), db.clone()).next().is_none());
+    }
+
+    #[test]
+    fn test_solve_detects_last_answer() {
+        let db = program("p(a). p(b). q(a).");
+        let mut answers = solve(&query("?- p(X)."), db.clone());
+        answers.next();
+        assert!(!answers.is_exhausted());
+        answers.next();
+        assert!(answers.is_exhausted());
+
+        let mut answers = solve(&query("?- q(X)."), db);
+        answers.next();
+        assert!(answers.is_exhausted());
+    }
+
+    #[test]
+    fn test_solve_respects_existing_bindings() {
+        let db = program("same(X, X). p(a). p(b). q(b).");
+        assert_eq!(solve(&query("?- same(A, A)."), db.clone()).count(), 1);
+        let answers: Vec<Substitution> = solve(&query("?- p(X), q(X)."), db.clone()).map(Result::unwrap).collect();
+        assert_eq!(answers.len(), 1);
+        assert_eq!(answers[0].get("X").unwrap(), &Term::Constant("b".to_string()));
+    }
+
+    #[test]
+    fn test_deep_recursion_shares_the_continuation() {
+        // every call of count/2 leaves S is S0+1 behind, so the continuation grows as deep as the recursion
+        let db = program("count(0, 0) :- !.\ncount(N, S) :- N1 is N - 1, count(N1, S0), S is S0 + 1.");
+        let mut solutions = solve(&query("?- count(10000, S)."), db);
+        let answer = solutions.next().unwrap().unwrap();
+        assert_eq!(answer.get("S"), Some(&Term::Number(Number::Integer(10000))));
+        assert!(solutions.next().is_none());
+    }
+
+    #[test]
+    fn test_resolution_tree_prunes_failed_branches() {
+        let db = program("p(X) :- q(X). p(c). q(a). q(b).");
+        let tree = resolve_query(&query("?- p(X)."), &db);
+        assert_eq!(tree.children.len(), 2);
+        assert_eq!(tree.children[0].children.len(), 2);
+
+        let results = extract_query_results(&tree, &["X".to_string()]);
+        let xs: Vec<Term> = results.iter().map(|s| s.get("X").unwrap().clone()).collect();
+        assert_eq!(xs, vec![
+            Term::Constant("a".to_string()),
+            Term::Constant("b".to_string()),
+            Term::Constant("c".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn test_cut_commits_to_first_clause_answer() {
+        let db = program("
+            p(a). p(b). p(c).
+            first(X) :- p(X), !.
+            r(X) :- first(X).
+            r(d).
+            s(a) :- !.
+            s(b).
+        ");
+        let xs = |q: &str| -> Vec<Term> {
+            solve(&query(q), db.clone()).map(|s| s.unwrap().get("X").unwrap().clone()).collect()
+        };
+
+        assert_eq!(xs("?- first(X)."), vec![Term::Constant("a".to_string())]);
+        // the cut is local to first/1, so the second r/1 clause is still tried
+        assert_eq!(xs("?- r(X)."), vec![Term::Constant("a".to_string()), Term::Constant("d".to_string())]);
+        // cut also discards the remaining clauses of the predicate it appears in
+        assert_eq!(xs("?- s(X)."), vec![Term::Constant("a".to_string())]);
+        // a cut in the query prunes every alternative before it
+        assert_eq!(xs("?- p(X), !."), vec![Term::Constant("a".to_string())]);
+    }
+
+    #[test]
+    fn test_cut_keeps_alternatives_after_it() {
+        let db = program("
+            p(a). p(b). q(c). q(d).
+            t(X, Y) :- p(X), !, q(Y).
+        ");
+        let answers: Vec<Substitution> = solve(&query("?- t(X, Y)."), db).map(Result::unwrap).collect();
+        assert_eq!(answers.len(), 2);
+        assert!(answers.iter().all(|s| s.get("X").unwrap() == &Term::Constant("a".to_string())));
+    }
+
+    #[test]
+    fn test_cut_in_resolution_tree() {
+        let db = program("p(a). p(b). first(X) :- p(X), !.");
+        let tree = resolve_query(&query("?- first(X)."), &db);
+        // first(X_1) -> p(a) -> !
+        assert_eq!(tree.children.len(), 1);
+        assert_eq!(tree.children[0].children.len(), 1);
+        let cut = &tree.children[0].children[0].children[0];
+        assert_eq!(cut.goal, Some(Atom::new("!", vec![])));
+    }
+
+    #[test]
+    fn test_negation_as_failure() {
+        let db = program("
+            member(X, cons(X, T)).
+            member(X, cons(Y, T)) :- member(X, T).
+            p(a). p(b). q(b). q(c).
+            onlyq(X) :- q(X), \\+ p(X).
+        ");
+        let xs = |q: &str| -> Vec<Term> {
+            solve(&query(q), db.clone()).map(|s| s.unwrap().get("X").unwrap().clone()).collect()
+        };
+
+        assert_eq!(xs("?- onlyq(X)."), vec![Term::Constant("c".to_string())]);
+        assert_eq!(xs("?- q(X), not(p(X))."), vec![Term::Constant("c".to_string())]);
+        assert_eq!(solve(&query("?- \\+ member(c, cons(a, cons(b, nil)))."), db.clone()).count(), 1);
+        assert_eq!(solve(&query("?- \\+ member(b, cons(a, cons(b, nil)))."), db.clone()).count(), 0);
+    }
+
+    #[test]
+    fn test_negation_does_not_bind() {
+        let db = program("p(a).");
+        // double negation proves the goal but leaves X unbound
+        let answers: Vec<Substitution> = solve(&query("?- \\+ \\+ p(X)."), db.clone()).map(Result::unwrap).collect();
+        assert_eq!(answers.len(), 1);
+        assert_eq!(answers[0].get("X").unwrap(), &Term::Variable("X".to_string()));
+        assert_eq!(solve(&query("?- \\+ \\+ p(b)."), db).count(), 0);
+    }
+
+    #[test]
+    fn test_negation_floundering() {
+        let db = program("p(a). q(b).");
+        // with X bound the negation behaves as expected ...
+        assert_eq!(solve(&query("?- q(X), \\+ p(X)."), db.clone()).count(), 1);
+        // ... but on a non-ground goal it reads "there is no X such that p(X)", so the same conjunction
+        // in the other order fails
+        assert_eq!(solve(&query("?- \\+ p(X), q(X)."), db.clone()).count(), 0);
+        // an unbound goal cannot be called
+        assert_eq!(solve(&query("?- not(G)."), db).next(), Some(Err(PrologError::Instantiation)));
+    }
+
+    #[test]
+    fn test_disjunction() {
+        let db = program("
+            p(a). q(b). r(c).
+            pq(X) :- p(X) ; q(X).
+        ");
+        let xs = |q: &str| -> Vec<Term> {
+            solve(&query(q), db.clone()).map(|s| s.unwrap().get("X").unwrap().clone()).collect()
+        };
+
+        assert_eq!(xs("?- pq(X)."), vec![Term::Constant("a".to_string()), Term::Constant("b".to_string())]);
+        assert_eq!(xs("?- (p(X) ; q(X) ; r(X))."), vec![
+            Term::Constant("a".to_string()),
+            Term::Constant("b".to_string()),
+            Term::Constant("c".to_string()),
+        ]);
+        assert_eq!(xs("?- (p(X) ; q(X)), q(X)."), vec![Term::Constant("b".to_string())]);
+    }
+
+    #[test]
+    fn test_fact_variables_are_local() {
+        let db = program("same(X, X).");
+        let answers: Vec<String> = solve(&query("?- same(a, X), same(b, Y)."), db)
+            .map(|s| format_answer(&s.unwrap()))
+            .collect();
+        assert_eq!(answers, vec!["X = a, Y = b"]);
+    }
+
+    #[test]
+    fn test_anonymous_variables() {
+        let db = program("pair(a, b). pair(c, c). first(X, _) :- pair(X, _).");
+        let answers = |q: &str| solve(&query(q), db.clone()).map(|s| format_answer(&s.unwrap())).collect::<Vec<_>>();
+        // the two '_' of a query do not have to be equal; neither they nor _A are part of the answer
+        assert_eq!(answers("?- pair(_, _)."), vec!["true", "true"]);
+        assert_eq!(answers("?- pair(X, _)."), vec!["X = a", "X = c"]);
+        assert_eq!(answers("?- pair(_A, _A)."), vec!["true"]);
+        // the variables the solver makes up cannot clash with the names of a query
+        assert_eq!(answers("?- X = _G1, Y = f(_), X = Y."), vec!["X = f(_G1), Y = f(_G1)"]);
+        assert_eq!(answers("?- X = f(_A, _A)."), vec!["X = f(_A, _A)"]);
+        assert_eq!(answers("?- first(X, z)."), vec!["X = a", "X = c"]);
+    }
+
+    #[test]
+    fn test_unknown_procedure() {
+        let db = program("p(a). q :- missing(X).");
+        let first = |q: &str| solve(&query(q), db.clone()).next();
+        assert_eq!(first("?- q."), Some(Err(PrologError::existence_error("procedure", "missing/1"))));
+        // a known name with another arity is still unknown
+        assert!(matches!(first("?- p(a, b)."), Some(Err(PrologError::Existence { .. }))));
+        // a known predicate that has no matching clause just fails
+        assert_eq!(first("?- p(b)."), None);
+        assert!(matches!(first("?- true, \\+ fail, \\+ false."), Some(Ok(_))));
+    }
+
+    #[test]
+    fn test_lists() {
+        let db = program("
+            app([], L, L).
+            app([H|T], L, [H|R]) :- app(T, L, R).
+            len([], 0).
+            len([H|T], N) :- len(T, M), N is M + 1.
+        ");
+        let answers: Vec<String> = solve(&query("?- app(X, Y, [1, 2])."), db.clone())
+            .map(|s| format_answer(&s.unwrap()))
+            .collect();
+        assert_eq!(answers, vec!["X = [], Y = [1,2]", "X = [1], Y = [2]", "X = [1,2], Y = []"]);
+
+        let answer = solve(&query("?- app([a|T], [c], [a, b, c]), len([x, y | T], N)."), db.clone()).next();
+        assert_eq!(format_answer(&answer.unwrap().unwrap()), "N = 3, T = [b]");
+        assert_eq!(solve(&query("?- app([a], [b], [b, a])."), db).count(), 0);
+    }
+
+    #[test]
+    fn test_if_then_else() {
+        let db = program("
+            p(a). p(b). q(b).
+            classify(X, R) :- ( q(X) -> eq(R, yes) ; eq(R, no) ).
+            eq(X, X).
+            true.
+        ");
+        let classify = |q: &str| -> Vec<Term> {
+            solve(&query(q), db.clone()).map(|s| s.unwrap().get("R").unwrap().clone()).collect()
+        };
+        assert_eq!(classify("?- classify(b, R)."), vec![Term::Constant("yes".to_string())]);
+        assert_eq!(classify("?- classify(a, R)."), vec![Term::Constant("no".to_string())]);
+
+        // the condition commits to its first solution
+        let answers: Vec<Substitution> = solve(&query("?- ( p(X) -> true ; true )."), db.clone()).map(Result::unwrap).collect();
+        assert_eq!(answers.len(), 1);
+        assert_eq!(answers[0].get("X").unwrap(), &Term::Constant("a".to_string()));
+
+        // else branch when the condition fails, with no bindings from the condition
+        let answers: Vec<Substitution> = solve(&query("?- ( q(a) -> p(X) ; q(X) )."), db.clone()).map(Result::unwrap).collect();
+        assert_eq!(answers.len(), 1);
+        assert_eq!(answers[0].get("X").unwrap(), &Term::Constant("b".to_string()));
+
+        // the then branch may still backtrack
+        assert_eq!(solve(&query("?- ( q(b) -> p(X) ; fail )."), db.clone()).count(), 2);
+
+        // if-then without else fails when the condition fails
+        assert_eq!(solve(&query("?- ( q(a) -> true )."), db.clone()).count(), 0);
+        assert_eq!(solve(&query("?- ( q(b) -> true )."), db).count(), 1);
+    }
+
+    #[test]
+    fn test_cut_in_control_constructs() {
+        let db = program("
+            p(a). p(b). p(c).
+            t(X) :- ( p(X), ! ; eq(X, d) ).
+            t(e).
+            u(X) :- ( p(X) -> ! ; true ).
+            u(f).
+            v(X) :- \\+ ( p(Y), ! , fail ), p(X).
+            eq(X, X).
+            true.
+        ");
+        let xs = |q: &str| -> Vec<Term> {
+            solve(&query(q), db.clone()).map(|s| s.unwrap().get("X").unwrap().clone()).collect()
+        };
+
+        // cut inside a nested conjunction in a disjunction cuts the whole clause
+        assert_eq!(xs("?- t(X)."), vec![Term::Constant("a".to_string())]);
+        // cut in the then branch is transparent as well
+        assert_eq!(xs("?- u(X)."), vec![Term::Constant("a".to_string())]);
+        // cut inside a negation is local to it
+        assert_eq!(xs("?- v(X).").len(), 3);
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let db = program("
+            factorial(0, 1) :- !.
+            factorial(N, F) :- N > 0, M is N - 1, factorial(M, G), F is N * G.
+            between(L, H, L) :- L =< H.
+            between(L, H, X) :- L < H, M is L + 1, between(M, H, X).
+        ");
+        let first = |q: &str, var: &str| -> Term {
+            solve(&query(q), db.clone()).next().unwrap().unwrap().get(var).unwrap().clone()
+        };
+
+        assert_eq!(first("?- factorial(10, F).", "F"), Term::Number(Number::Integer(3628800)));
+        assert_eq!(first("?- X is 7 / 2.", "X"), Term::Number(Number::Float(3.5)));
+        assert_eq!(
+            first("?- factorial(25, F).", "F"),
+            Term::Number(Number::BigInt("15511210043330985984000000".parse().unwrap()))
+        );
+        assert_eq!(solve(&query("?- factorial(25, F), F is 15511210043330985984 * 1000000."), db.clone()).count(), 1);
+        assert_eq!(solve(&query("?- X is 1r3 + 1r6, X =:= 1r2, X is 1 rdiv 2."), db.clone()).count(), 1);
+        assert_eq!(solve(&query("?- between(1, 5, X), X mod 2 =:= 0."), db.clone()).count(), 2);
+        assert_eq!(solve(&query("?- 1 =:= 1.0, 1 =\\= 2, 2 >= 2, 1 < 2.5."), db.clone()).count(), 1);
+        // is/2 unifies, so a bound left side acts as a check; integers and floats are distinct terms
+        assert_eq!(solve(&query("?- 3 is 1 + 2."), db.clone()).count(), 1);
+        assert_eq!(solve(&query("?- 3.0 is 1 + 2."), db).count(), 0);
+    }
+
+    #[test]
+    fn test_arithmetic_errors_abort_the_query() {
+        let db = program("p(a). p(b).");
+        let mut answers = solve(&query("?- p(X), Y is X + 1."), db.clone());
+        assert_eq!(answers.next(), Some(Err(PrologError::type_error("evaluable", "a/0"))));
+        assert!(answers.next().is_none());
+
+        assert_eq!(
+            solve(&query("?- X < 1."), db.clone()).next(),
+            Some(Err(PrologError::Instantiation))
+        );
+        // errors propagate out of negation and if-then-else conditions
+        assert_eq!(
+            solve(&query("?- \\+ X is 1 / 0."), db).next(),
+            Some(Err(PrologError::Evaluation("zero_divisor".to_string())))
+        );
+    }
+
+    #[test]
+    fn test_consult_runs_directives() {
+        let src = "p(1).\n:- p(1), p(1).\n:- q.\nq.\n:- initialization(q).\n:- op(700, xfx, ===>).\n:- foo(a).\n\
+                   :- dynamic(foo/1), discontiguous([bar/0, baz/2]).\n:- dynamic(foo).\n:- ensure_loaded(library(lists)).";
+        let (stmts, _) = Parser::from_source(src).unwrap().parse_program();
+        let (db, warnings) = consult(stmts, None);
+        // directives see only the clauses before them, initialization/1 waits for the whole program
+        let warnings: Vec<String> = warnings.iter().map(|w| w.to_string()).collect();
+        assert_eq!(warnings, vec![
+            "3:4: Goal (directive) q raised: Existence error: unknown procedure q/0",
+            "7:4: Goal (directive) foo(a) raised: Existence error: unknown procedure foo/1",
+            "9:12: Goal (directive) dynamic foo raised: Type error: predicate_indicator expected, found foo",
+        ]);
+        assert_eq!(solve(&query("?- q."), db).count(), 1);
+
+        let (stmts, _) = Parser::from_source("p(1).\n:- p(2).").unwrap().parse_program();
+        let warnings = consult(stmts, Some("p(1).\n:- p(2).")).1;
+        assert_eq!(warnings[0].to_string(), "2:4: Goal (directive) failed: p(2)\n:- p(2).\n   ^^^^");
+    }
+
+    #[test]
+    fn test_directive_errors_point_at_their_culprit() {
+        let src = ":- X is 1 + foo.\n:- true, (functor(F, g, a) ; true).\n:- Y = bar, atom_length(Y, 3).";
+        let (stmts, _) = Parser::from_source(src).unwrap().parse_program();
+        let warnings: Vec<String> = consult(stmts, Some(src)).1.iter().map(|w| w.to_string()).collect();
+        assert_eq!(warnings, vec![
+            "1:13: Goal (directive) X is 1+foo raised: Type error: evaluable expected, found foo/0\n:- X is 1 + foo.\n            ^^^",
+            "2:25: Goal (directive) true, functor(F, g, a);true raised: Type error: integer expected, found a\n\
+             :- true, (functor(F, g, a) ; true).\n                        ^",
+            "3:13: Goal (directive) Y=bar, atom_length(Y, 3) raised: Existence error: unknown procedure atom_length/2\n\
+             :- Y = bar, atom_length(Y, 3).\n            ^^^^^^^^^^^^^^^^^",
+        ]);
+    }
+
+    #[test]
+    fn test_consult_skips_clauses_for_builtins() {
+        let src = "atom(foo).\nlength(L, N) :- N = 0.\np(1).";
+        let (stmts, _) = Parser::from_source(src).unwrap().parse_program();
+        let (db, warnings) = consult(stmts, None);
+        let warnings: Vec<String> = warnings.iter().map(|w| w.to_string()).collect();
+        assert_eq!(warnings, vec![
+            "1:1: Permission error: cannot modify static_procedure atom/1",
+            "2:1: Permission error: cannot modify static_procedure length/2",
+        ]);
+        assert_eq!(solve(&query("?- atom(bar)."), db.clone()).count(), 1);
+        assert_eq!(solve(&query("?- p(X)."), db).count(), 1);
+    }
+
+    #[test]
+    fn test_op_changes_the_operators_of_the_database() {
+        let (stmts, _) = Parser::from_source(":- op(700, xfx, ===>).").unwrap().parse_program();
+        let (db, warnings) = consult(stmts, None);
+        assert!(warnings.is_empty());
+        assert_eq!(db.operators().infix("===>"), Some((700, OpType::Xfx)));
+
+        assert_eq!(solve(&query("?- op(200, xfy, likes), op(0, xfx, ===>)."), db.clone()).count(), 1);
+        assert_eq!(db.operators().infix("likes"), Some((200, OpType::Xfy)));
+        assert_eq!(db.operators().infix("===>"), None);
+        // a failed op/3 leaves the table as it was
+        assert!(solve(&query("?- op(700, xfx, [loves, ','])."), db.clone()).next().unwrap().is_err());
+        assert_eq!(db.operators().infix("loves"), None);
+    }
+
+    #[test]
+    fn test_declaration_errors() {
+        let error = |src: &str| solve(&query(src), program("")).next().unwrap().unwrap_err().to_string();
+        assert_eq!(error("?- op(1201, xfx, a)."), "Domain error: operator_priority expected, found 1201");
+        assert_eq!(error("?- dynamic(foo/a)."), "Type error: integer expected, found a");
+        assert_eq!(error("?- dynamic([foo/ -1])."), "Domain error: not_less_than_zero expected, found -1");
+        assert_eq!(error("?- discontiguous(X/1)."), "Instantiation error");
+        assert_eq!(error("?- ensure_loaded(foo)."), "Existence error: unknown source_sink foo");
+        assert_eq!(error("?- use_module(apply)."), "Existence error: unknown source_sink apply");
+        assert_eq!(error("?- use_module(library(no_such_lib))."), "Existence error: unknown source_sink library(no_such_lib)");
+        assert_eq!(error("?- ensure_loaded(library(L))."), "Instantiation error");
+        assert_eq!(error("?- set_prolog_flag(double_quotes, text)."), "Domain error: flag_value expected, found double_quotes+text");
+        assert_eq!(error("?- set_prolog_flag(bounded, true)."), "Domain error: prolog_flag expected, found bounded");
+        assert_eq!(error("?- set_prolog_flag(F, codes)."), "Instantiation error");
+        let db = ClauseStore::from(program(""));
+        assert!(solve(&query("?- set_prolog_flag(double_quotes, atom)."), db.clone()).next().is_some());
+        assert_eq!(db.double_quotes(), DoubleQuotes::Atom);
+        assert!(solve(&query("?- use_module(library(apply)), use_module(library(lists), [append/3])."), program("")).next().is_some());
+        assert_eq!(error("?- initialization(1)."), "Type error: callable expected, found 1");
+    }
+
+    #[test]
+    fn test_unification_and_comparison() {
+        let db = program("p(a). p(b).");
+        let answers = |src: &str| -> Vec<String> {
+            solve(&query(src), db.clone())
+                .map(|answer| answer.map_or_else(|e| e.to_string(), |subs| format_answer(&subs)))
+                .collect()
+        };
+        assert_eq!(answers("?- X = f(Y), Y = a."), vec!["X = f(a), Y = a"]);
+        assert_eq!(answers("?- X = f(X)."), Vec::<String>::new());
+        assert_eq!(answers("?- p(X), X \\= a."), vec!["X = b"]);
+        assert_eq!(answers("?- X \\= a."), Vec::<String>::new());
+        assert_eq!(answers("?- f(X) == f(X), f(X) \\== f(Y), X \\== a.").len(), 1);
+        assert_eq!(answers("?- X == a."), Vec::<String>::new());
+        assert_eq!(answers("?- 1 @< a, a @< f(a), 1.0 @< 1, f(b) @< g(a), g(b) @< f(a, a)."), vec!["true"]);
+        assert_eq!(answers("?- b @>= a, a @=< a, f(b) @> f(a), X @< 1."), vec!["true"]);
+        assert_eq!(answers("?- compare(O, 1, a), compare(=, f(a), f(a))."), vec!["O = <"]);
+        assert_eq!(answers("?- compare(<, b, a)."), Vec::<String>::new());
+        assert_eq!(answers("?- compare(less, 1, 2)."), vec!["Domain error: order expected, found less"]);
+        assert_eq!(answers("?- compare(1, 1, 2)."), vec!["Type error: atom expected, found 1"]);
+    }
+
+    #[test]
+    fn test_type_checks() {
+        let db = program("p(X, Y) :- X = f(Y).");
+        let succeeds = |src: &str| solve(&query(src), db.clone()).next().is_some();
+        assert!(succeeds("?- var(X), X = a, nonvar(X), atom(X), atomic(X), callable(X)."));
+        assert!(succeeds("?- p(X, Y), var(Y), compound(X), callable(X), \\+ ground(X), Y = 1, ground(X)."));
+        assert!(succeeds("?- number(1), number(1.5), integer(1), float(1.5), integer(100000000000000000000)."));
+        assert!(succeeds("?- atom([]), is_list([]), is_list([a, b]), \\+ is_list([a | _]), \\+ is_list(a)."));
+        assert!(!succeeds("?- atom(1)."));
+        assert!(!succeeds("?- atom(f(a))."));
+        assert!(!succeeds("?- atomic(X)."));
+        assert!(!succeeds("?- callable(1)."));
+        assert!(!succeeds("?- compound(a)."));
+        assert!(!succeeds("?- integer(1.0)."));
+        assert!(!succeeds("?- string(a)."));
+    }
+
+    #[test]
+    fn test_term_inspection() {
+        let answers = |src: &str| -> Vec<String> {
+            solve(&query(src), program(""))
+                .map(|answer| answer.map_or_else(|e| e.to_string(), |subs| format_answer(&subs)))
+                .collect()
+        };
+        assert_eq!(answers("?- functor(f(a, b), N, A)."), vec!["A = 2, N = f"]);
+        assert_eq!(answers("?- functor(a, N, A), functor(1.5, M, B)."), vec!["A = 0, B = 0, M = 1.5, N = a"]);
+        assert_eq!(answers("?- functor(T, f, 2), T = f(A, B), var(A), A \\== B."), vec!["T = f(A, B)"]);
+        assert_eq!(answers("?- functor(T, foo, 0), functor(U, 3, 0)."), vec!["T = foo, U = 3"]);
+        assert_eq!(answers("?- functor(T, N, 1)."), vec!["Instantiation error"]);
+        assert_eq!(answers("?- functor(T, foo(a), 1)."), vec!["Type error: atomic expected, found foo(a)"]);
+        assert_eq!(answers("?- functor(T, 1, 1)."), vec!["Type error: atom expected, found 1"]);
+        assert_eq!(answers("?- functor(T, f, -1)."), vec!["Domain error: not_less_than_zero expected, found -1"]);
+
+        assert_eq!(answers("?- arg(2, f(a, b, c), X)."), vec!["X = b"]);
+        assert_eq!(answers("?- arg(N, f(a, b), X)."), vec!["N = 1, X = a", "N = 2, X = b"]);
+        assert_eq!(answers("?- arg(0, f(a), X)."), Vec::<String>::new());
+        assert_eq!(answers("?- arg(1, f(X), b)."), vec!["X = b"]);
+        assert_eq!(answers("?- arg(1, a, X)."), vec!["Type error: compound expected, found a"]);
+        assert_eq!(answers("?- arg(a, f(a), X)."), vec!["Type error: integer expected, found a"]);
+
+        assert_eq!(answers("?- f(a, X) =.. L."), vec!["L = [f,a,X]"]);
+        assert_eq!(answers("?- T =.. [g, 1, Y], a =.. M, N =.. [1]."), vec!["M = [a], N = 1, T = g(1, Y)"]);
+        assert_eq!(answers("?- T =.. [X, a]."), vec!["Instantiation error"]);
+        assert_eq!(answers("?- T =.. [f | _]."), vec!["Instantiation error"]);
+        assert_eq!(answers("?- T =.. []."), vec!["Domain error: non_empty_list expected, found []"]);
+        assert_eq!(answers("?- T =.. [1, a]."), vec!["Type error: atom expected, found 1"]);
+        assert_eq!(answers("?- T =.. [f(a), b]."), vec!["Type error: atomic expected, found f(a)"]);
+
+        assert_eq!(answers("?- X = f(Y, Y, Z), copy_term(X, C), C = f(a, B, b)."), vec!["B = a, C = f(a, a, b), X = f(Y, Y, Z)"]);
+        assert_eq!(answers("?- copy_term(f(X), f(Y)), X == Y."), Vec::<String>::new());
+        // the copy has new variables, shared where the original shares them
+        assert_eq!(answers("?- X = f(Y, Y, Z), copy_term(X, C), C = f(A, A2, B), A == A2, A \\== B, A \\== Y, B \\== Z.").len(), 1);
+        assert_eq!(answers("?- copy_term(X-Y, Z), Z = A-B, A \\== B, A \\== X."), vec!["Z = A-B"]);
+    }
+
+    #[test]
+    fn test_call() {
+        let db = program("
+            p(1). p(2). p(3).
+            add(X, Y, Z) :- Z is X + Y.
+            first(X) :- call((p(X), !)).
+            twice(G) :- G, G.
+            apply(G, X) :- call(G, X).
+        ");
+        let answers = |src: &str| -> Vec<String> {
+            solve(&query(src), db.clone())
+                .map(|answer| answer.map_or_else(|e| e.to_string(), |subs| format_answer(&subs)))
+                .collect()
+        };
+        assert_eq!(answers("?- call(p(X))."), vec!["X = 1", "X = 2", "X = 3"]);
+        assert_eq!(answers("?- call(add(1), 2, Z), call(add, Z, 3, W)."), vec!["W = 6, Z = 3"]);
+        assert_eq!(answers("?- G = p(X), G."), vec!["G = p(1), X = 1", "G = p(2), X = 2", "G = p(3), X = 3"]);
+        assert_eq!(answers("?- twice(p(X))."), vec!["X = 1", "X = 2", "X = 3"]);
+        assert_eq!(answers("?- apply(p, 2)."), vec!["true"]);
+        assert_eq!(answers("?- X = true, (X, X ; fail)."), vec!["X = true"]);
+        // the cut inside call/1 only commits the call itself, not the clause or query around it
+        assert_eq!(answers("?- first(X)."), vec!["X = 1"]);
+        assert_eq!(answers("?- p(X), call(!)."), vec!["X = 1", "X = 2", "X = 3"]);
+        assert_eq!(answers("?- p(X), call((!, X > 1))."), vec!["X = 2", "X = 3"]);
+
+        assert_eq!(answers("?- call(G)."), vec!["Instantiation error"]);
+        assert_eq!(answers("?- G."), vec!["Instantiation error"]);
+        assert_eq!(answers("?- call(1, a)."), vec!["Type error: callable expected, found 1"]);
+        assert_eq!(answers("?- (fail ; X = 1), (true, X)."), vec!["Type error: callable expected, found 1"]);
+        assert_eq!(answers("?- (fail, G)."), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_aggregate_all() {
+        let db = program("p(1). p(3). p(2). p(3). q(a). age(ann, 30). age(bob, 25).");
+        let answers = |src: &str| -> Vec<String> {
+            solve(&query(src), db.clone())
+                .map(|answer| answer.map_or_else(|e| e.to_string(), |subs| format_answer(&subs)))
+                .collect()
+        };
+        assert_eq!(answers("?- aggregate_all(count, p(_), C)."), vec!["C = 4"]);
+        assert_eq!(answers("?- aggregate_all(count, fail, C)."), vec!["C = 0"]);
+        assert_eq!(answers("?- aggregate_all(sum(X), p(X), S)."), vec!["S = 9"]);
+        assert_eq!(answers("?- aggregate_all(sum(X * 1.5), p(X), S)."), vec!["S = 13.5"]);
+        assert_eq!(answers("?- aggregate_all(max(X), p(X), M), aggregate_all(min(A), age(_, A), N)."), vec!["M = 3, N = 25"]);
+        assert_eq!(answers("?- aggregate_all(max(X), fail, M)."), Vec::<String>::new());
+        assert_eq!(answers("?- aggregate_all(bag(X), p(X), B), aggregate_all(set(X), p(X), S)."), vec!["B = [1,3,2,3], S = [1,2,3]"]);
+        assert_eq!(answers("?- aggregate_all(bag(N-A), age(N, A), L)."), vec!["L = [ann-30,bob-25]"]);
+        assert_eq!(answers("?- aggregate_all(sum(X), q(X), S)."), vec!["Type error: evaluable expected, found a/0"]);
+        assert_eq!(answers("?- aggregate_all(avg(X), p(X), S)."), vec!["Domain error: aggregate_spec expected, found avg(X)"]);
+        assert_eq!(answers("?- aggregate_all(count, G, C)."), vec!["Instantiation error"]);
+    }
+
+    #[test]
+    fn test_findall() {
+        let db = program("p(1, a). p(2, b). p(3, a). q(X, Y) :- p(Y, X).");
+        let answers = |src: &str| -> Vec<String> {
+            solve(&query(src), db.clone())
+                .map(|answer| answer.map_or_else(|e| e.to_string(), |subs| format_answer(&subs)))
+                .collect()
+        };
+        assert_eq!(answers("?- findall(X-Y, p(X, Y), L)."), vec!["L = [1-a,2-b,3-a]"]);
+        assert_eq!(answers("?- findall(X, fail, L)."), vec!["L = []"]);
+        assert_eq!(answers("?- findall(X, (p(X, _), X > 1), L, [end])."), vec!["L = [2,3,end]"]);
+        // every instance has its own variables
+        assert_eq!(answers("?- findall(f(V), (true ; true), [f(A), f(B)]), A \\== B.").len(), 1);
+        assert_eq!(answers("?- findall(X, G, L)."), vec!["Instantiation error"]);
+        assert_eq!(answers("?- findall(X, 4, L)."), vec!["Type error: callable expected, found 4"]);
+        assert_eq!(answers("?- findall(X, p(X, _), [A | b])."), vec!["Type error: list expected, found [A|b]"]);
+    }
+
+    #[test]
+    fn test_bagof_and_setof() {
+        // the examples of the ISO standard for bagof/3 and setof/3
+        let db = program("
+            a(1, f(_)). a(2, f(_)).
+            c(1, g(_, b)). c(2, g(_, a)). c(3, g(_, b)).
+            b(1, 1). b(1, 1). b(1, 2). b(2, 1). b(2, 2). b(2, 2).
+            d(1, 1). d(1, 2). d(1, 1). d(2, 2). d(2, 1). d(2, 2).
+        ");
+        let answers = |src: &str| -> Vec<String> {
+            solve(&query(src), db.clone())
+                .map(|answer| answer.map_or_else(|e| e.to_string(), |subs| format_answer(&subs)))
+                .collect()
+        };
+        assert_eq!(answers("?- bagof(X, (X = 1 ; X = 2), S)."), vec!["S = [1,2]"]);
+        assert_eq!(answers("?- bagof(X, (X = Y ; X = Z), L)."), vec!["L = [Y,Z]"]);
+        assert_eq!(answers("?- bagof(X, fail, S)."), Vec::<String>::new());
+        let answers_with_y = answers("?- bagof(X, (X = Y ; X = Z ; Y = 1), L).");
+        assert_eq!((answers_with_y.len(), answers_with_y[0].as_str()), (2, "L = [Y,Z]"));
+        assert_eq!(answers("?- bagof(X, (X = Y ; X = Z ; Y = 1), L), L = [V], var(V)."), vec!["L = [V], Y = 1"]);
+        assert_eq!(answers("?- bagof(X, Y^((X = 1, Y = 1) ; (X = 2, Y = 2)), S)."), vec!["S = [1,2]"]);
+        assert_eq!(answers("?- bagof(X, b(X, Y), L)."), vec!["L = [1,1,2], Y = 1", "L = [1,2,2], Y = 2"]);
+        assert_eq!(answers("?- bagof(X, Y^Z^(Y = 1 ; Z = 2), L).").len(), 1);
+        assert_eq!(answers("?- bagof(X, Y^G, L)."), vec!["Instantiation error"]);
+        assert_eq!(answers("?- bagof(X, 1, L)."), vec!["Type error: callable expected, found 1"]);
+        assert_eq!(answers("?- bagof(X, a(X, Y), L), Y = f(V), var(V)."), vec!["L = [1,2], Y = f(V)"]);
+        // the free variable is bound to variants that are not neighbours in the standard order
+        assert_eq!(answers("?- bagof(X, c(X, W), L), W = g(V, B), var(V)."), vec![
+            "B = b, L = [1,3], W = g(V, b)",
+            "B = a, L = [2], W = g(V, a)",
+        ]);
+
+        assert_eq!(answers("?- setof(X, (X = 1 ; X = 2 ; X = 1), S)."), vec!["S = [1,2]"]);
+        assert_eq!(answers("?- setof(X, (X = b ; X = c ; X = a), S)."), vec!["S = [a,b,c]"]);
+        assert_eq!(answers("?- setof(X-Z, Y^d(X, Y), L).").len(), 1);
+        assert_eq!(answers("?- setof(X, d(X, Y), L)."), vec!["L = [1,2], Y = 1", "L = [1,2], Y = 2"]);
+        assert_eq!(answers("?- setof(X, Y^b(X, Y), L)."), vec!["L = [1,2]"]);
+        assert_eq!(answers("?- setof(X, (X = f(U, b) ; X = f(V, c)), [f(a, A), f(B, C)])."), vec![
+            "A = b, B = V, C = c, U = a"
+        ]);
+    }
+
+    #[test]
+    fn test_assert_and_retract() {
+        let (stmts, _) = Parser::from_source(":- dynamic(count/1).\ncount(0). p(1). p(2). p(3).").unwrap().parse_program();
+        let (db, _) = consult(stmts, None);
+        let answers = |src: &str| -> Vec<String> {
+            solve(&query(src), db.clone())
+                .map(|answer| answer.map_or_else(|e| e.to_string(), |subs| format_answer(&subs)))
+                .collect()
+        };
+        assert_eq!(answers("?- assertz(q(b)), asserta(q(a)), assert((r(X) :- q(X), X \\== a))."), vec!["true"]);
+        // changes made by one query are seen by the next ones on the same database
+        assert_eq!(answers("?- q(X)."), vec!["X = a", "X = b"]);
+        assert_eq!(answers("?- r(X)."), vec!["X = b"]);
+        assert_eq!(answers("?- retract(count(C)), C1 is C + 1, assertz(count(C1)), count(N)."), vec!["C = 0, C1 = 1, N = 1"]);
+        assert_eq!(answers("?- retract((r(X) :- B))."), vec!["B = (q(X),X\\==a)"]);
+        assert_eq!(answers("?- r(X)."), Vec::<String>::new());
+        assert_eq!(answers("?- retract(q(X))."), vec!["X = a", "X = b"]);
+        assert_eq!(answers("?- q(X)."), Vec::<String>::new());
+
+        // the logical update view: a running call sees the clauses as they were when it started
+        assert_eq!(answers("?- assertz(s(1)), s(X), Y is X + 1, assertz(s(Y))."), vec!["X = 1, Y = 2"]);
+        assert_eq!(answers("?- s(X), retract(s(_)), fail ; findall(X, s(X), L)."), vec!["L = []"]);
+
+        assert_eq!(answers("?- assertz(t(1)), assertz(t(2)), retractall(t(1)), findall(X, t(X), L)."), vec!["L = [2]"]);
+        assert_eq!(answers("?- retractall(u(_)), u(X)."), Vec::<String>::new());
+        assert_eq!(answers("?- abolish(t/1), t(X)."), vec!["Existence error: unknown procedure t/1"]);
+
+        assert_eq!(answers("?- assertz(p(4))."), vec!["Permission error: cannot modify static_procedure p/1"]);
+        assert_eq!(answers("?- retract(p(1))."), vec!["Permission error: cannot modify static_procedure p/1"]);
+        assert_eq!(answers("?- abolish(p/1)."), vec!["Permission error: cannot modify static_procedure p/1"]);
+        assert_eq!(answers("?- assertz(atom(a))."), vec!["Permission error: cannot modify static_procedure atom/1"]);
+        assert_eq!(answers("?- assertz(X)."), vec!["Instantiation error"]);
+        assert_eq!(answers("?- assertz((foo :- 1))."), vec!["Type error: callable expected, found 1"]);
+        assert_eq!(answers("?- assertz(1)."), vec!["Type error: callable expected, found 1"]);
+    }
+
+    #[test]
+    fn test_dynamic_declarations() {
+        let (stmts, _) = Parser::from_source(":- dynamic(seen/1), dynamic([a/0, b/2]).\n:- dynamic(p/1).\np(1).").unwrap().parse_program();
+        let (db, warnings) = consult(stmts, None);
+        assert!(warnings.is_empty());
+        // a dynamic predicate without clauses fails instead of raising an existence error
+        assert_eq!(solve(&query("?- seen(X)."), db.clone()).count(), 0);
+        assert_eq!(solve(&query("?- a."), db.clone()).count(), 0);
+        assert_eq!(solve(&query("?- assertz(p(2)), p(X)."), db.clone()).count(), 2);
+
+        let (stmts, _) = Parser::from_source(":- dynamic c/1, d/2.").unwrap().parse_program();
+        let (db, warnings) = consult(stmts, None);
+        assert!(warnings.is_empty());
+        assert_eq!(solve(&query("?- c(X) ; d(X, Y)."), db.clone()).count(), 0);
+        assert_eq!(
+            solve(&query("?- dynamic(length/2)."), db).next(),
+            Some(Err(PrologError::permission_error("modify", "static_procedure", "length/2")))
+        );
+    }
+
+    #[test]
+    fn test_indexed_calls() {
+        let facts: String = (0..100).map(|i| format!("edge(n{}, n{}). ", i, (i * 7) % 100)).collect();
+        let db: ClauseStore = program(&format!("{}path(X, Y) :- edge(X, Z), edge(Z, Y).", facts)).into();
+        // a call that can only match one clause leaves no choicepoint behind
+        let mut solutions = solve(&query("?- edge(n3, X)."), db.clone());
+        assert_eq!(format_answer(&solutions.next().unwrap().unwrap()), "X = n21");
+        assert!(solutions.is_exhausted());
+        let mut solutions = solve(&query("?- edge(X, n21)."), db.clone());
+        assert_eq!(format_answer(&solutions.next().unwrap().unwrap()), "X = n3");
+        assert!(solutions.is_exhausted());
+        let mut solutions = solve(&query("?- path(n3, X)."), db.clone());
+        assert_eq!(format_answer(&solutions.next().unwrap().unwrap()), "X = n47");
+        assert!(solutions.is_exhausted());
+        assert_eq!(solve(&query("?- edge(X, Y)."), db).count(), 100);
+    }
+
+    // cargo test --release bench_indexing -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_indexing() {
+        use std::time::Instant;
+        const ROWS: usize = 200_000;
+        const CALLS: usize = 200;
+        let facts: String = (0..ROWS).map(|i| format!("row({}, k{}). ", i, (i * 7919) % ROWS)).collect();
+        let db: ClauseStore = program(&facts).into();
+        let points: String = (0..ROWS).map(|i| format!("point({}.5, {}). ", i, i)).collect();
+        let points: ClauseStore = program(&points).into();
+        let cells: String = (0..ROWS).map(|i| format!("cell({}, 0). ", i)).collect();
+        let (stmts, _) = Parser::from_source(&format!(":- dynamic(cell/2).\n{}", cells)).unwrap().parse_program();
+        let (cells, _) = consult(stmts, None);
+        // the first call builds the index, so it is timed apart from the others
+        let time = |name: &str, db: &ClauseStore, goal: &dyn Fn(usize) -> String| {
+            let call = |i: usize| {
+                let start = Instant::now();
+                assert_eq!(solve(&query(&format!("?- {}.", goal((i * 997) % ROWS))), db.clone()).count(), 1);
+                start.elapsed()
+            };
+            let first = call(0);
+            let elapsed = (1..CALLS).map(call).sum::<std::time::Duration>() / (CALLS - 1) as u32;
+            println!("{:<28} first call {:>10.2?}, then {:>10.2?} per call", name, first, elapsed);
+            elapsed
+        };
+        let scan = time("scan: row(X, V), X == K", &db, &|key| format!("row(X, V), X == {}", key));
+        let first = time("first argument: row(K, V)", &db, &|key| format!("row({}, V)", key));
+        let second = time("just in time: row(I, k..)", &db, &|key| format!("row(I, k{})", key));
+        let speedup = |indexed: std::time::Duration| scan.as_secs_f64() / indexed.as_secs_f64();
+        let float = time("float key: point(K.5, I)", &points, &|key| format!("point({}.5, I)", key));
+        println!(
+            "speedup: {:.0}x first argument, {:.0}x second argument, {:.0}x float key",
+            speedup(first), speedup(second), speedup(float)
+        );
+        // a dynamic table whose rows are retracted and asserted again, which keeps the index up to date
+        let update = |key| format!("retract(cell({0}, V)), W is V + 1, assertz(cell({0}, W))", key);
+        time("update: retract, assertz", &cells, &update);
+        time("lookup after updates", &cells, &|key| format!("cell({}, 1)", key));
+    }
+}
@@ -1,14 +1,209 @@
+use crate::error::{PrologError, SourceError};
+use crate::operators::Operators;
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{ToPrimitive, Zero};
+use std::fmt;
+use std::ops::Neg;
+use std::sync::Arc;
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Term {
     Constant(String),
     Variable(String),
+    Number(Number),
+    String(String), // a double-quoted string when the double_quotes flag is `string`
     Compound { name: String, args: Vec<Term> },
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+// Integers that fit in 64 bits are kept as i64 and transparently promoted to BigInt when they grow;
+// a BigInt or Rational is always normalised back to the smallest representation of its value.
+#[derive(Debug, Clone)]
+pub enum Number {
+    Integer(i64),
+    BigInt(BigInt),
+    Rational(BigRational),
+    Float(f64),
+}
+
+impl Number {
+    pub fn from_bigint(n: BigInt) -> Number {
+        match n.to_i64() {
+            Some(i) => Number::Integer(i),
+            None => Number::BigInt(n),
+        }
+    }
+
+    pub fn from_rational(r: BigRational) -> Number {
+        if r.is_integer() {
+            Number::from_bigint(r.to_integer())
+        } else {
+            Number::Rational(r)
+        }
+    }
+}
+
+impl Neg for Number {
+    type Output = Number;
+
+    fn neg(self) -> Number {
+        match self {
+            Number::Integer(i) => match i.checked_neg() {
+                Some(i) => Number::Integer(i),
+                None => Number::from_bigint(-BigInt::from(i)),
+            },
+            Number::BigInt(n) => Number::from_bigint(-n),
+            Number::Rational(r) => Number::Rational(-r),
+            Number::Float(f) => Number::Float(-f),
+        }
+    }
+}
+
+// Numbers are equal only if they are of the same type, so 1 and 1.0 do not unify.
+// Floats are compared by their total order so that Term can stay Eq.
+impl PartialEq for Number {
+    fn eq(&self, other: &Number) -> bool {
+        match (self, other) {
+            (Number::Integer(a), Number::Integer(b)) => a == b,
+            (Number::BigInt(a), Number::BigInt(b)) => a == b,
+            (Number::Rational(a), Number::Rational(b)) => a == b,
+            (Number::Float(a), Number::Float(b)) => a.total_cmp(b).is_eq(),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Number {}
+
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Number::Integer(n) => write!(f, "{}", n),
+            Number::BigInt(n) => write!(f, "{}", n),
+            Number::Rational(r) => write!(f, "{}r{}", r.numer(), r.denom()),
+            // always show a fraction so that floats read back as floats, e.g. 1.0 and 1.0e20
+            Number::Float(x) => {
+                let s = format!("{:?}", x);
+                if x.is_finite() && !s.contains('.') {
+                    match s.split_once('e') {
+                        Some((mantissa, exponent)) => write!(f, "{}.0e{}", mantissa, exponent),
+                        None => write!(f, "{}.0", s),
+                    }
+                } else {
+                    write!(f, "{}", s)
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Atom {
     pub name: String,
     pub args: Vec<Term>,
+    pub span: Span, // where the atom was read; atoms built while solving have the default span
+    pub arg_spans: Arc<[TermSpan]>, // where each argument was read; empty for atoms built while solving
+}
+
+// Where a term and each of its arguments were read, in the shape of the term. Terms are plain values that
+// are copied and rebuilt while solving, so their spans are kept beside them, on the atom they belong to.
+#[derive(Debug, Clone, Default)]
+pub struct TermSpan {
+    pub span: Span,
+    pub args: Vec<TermSpan>, // empty when the arguments were not written out, e.g. the codes of a string
+}
+
+impl TermSpan {
+    pub fn new(span: Span, args: Vec<TermSpan>) -> TermSpan {
+        TermSpan { span, args }
+    }
+
+    // The span of the first subterm of `term`, which was read at `self`, for which `found` holds,
+    // looking at a term before its arguments
+    pub fn find(&self, term: &Term, found: &impl Fn(&Term) -> bool) -> Option<Span> {
+        if found(term) {
+            return Some(self.span);
+        }
+        match term {
+            Term::Compound { args, .. } => args.iter().zip(&self.args).find_map(|(arg, span)| span.find(arg, found)),
+            _ => None,
+        }
+    }
+}
+
+// Atoms are equal when they have the same name and arguments, wherever they were read
+impl PartialEq for Atom {
+    fn eq(&self, other: &Atom) -> bool {
+        self.name == other.name && self.args == other.args
+    }
+}
+
+impl Eq for Atom {}
+
+// Goals are atoms, but meta-predicates such as \+ receive their goal as a term argument
+impl From<Atom> for Term {
+    fn from(atom: Atom) -> Term {
+        if atom.args.is_empty() {
+            Term::Constant(atom.name)
+        } else {
+            Term::Compound { name: atom.name, args: atom.args }
+        }
+    }
+}
+
+// Lists are '.'/2 cells ending in the empty list '[]', so [a, b | T] is '.'(a, '.'(b, T))
+impl Term {
+    pub fn nil() -> Term {
+        Term::Constant("[]".to_string())
+    }
+
+    pub fn cons(head: Term, tail: Term) -> Term {
+        Term::Compound { name: ".".to_string(), args: vec![head, tail] }
+    }
+
+    // Builds the list of `items` followed by `tail`
+    pub fn list(items: Vec<Term>, tail: Term) -> Term {
+        items.into_iter().rev().fold(tail, |list, item| Term::cons(item, list))
+    }
+
+    // Splits a list into its elements and the term that ends it ('[]' for a proper list)
+    pub fn list_items(&self) -> (Vec<&Term>, &Term) {
+        let mut items = vec![];
+        let mut rest = self;
+        while let Term::Compound { name, args } = rest {
+            if name != "." || args.len() != 2 {
+                break;
+            }
+            items.push(&args[0]);
+            rest = &args[1];
+        }
+        (items, rest)
+    }
+}
+
+impl Atom {
+    pub fn new(name: impl Into<String>, args: Vec<Term>) -> Atom {
+        Atom { name: name.into(), args, span: Span::default(), arg_spans: Arc::default() }
+    }
+
+    // The atom read as `term` at `spans`
+    pub fn with_spans(self, spans: &TermSpan) -> Atom {
+        Atom { span: spans.span, arg_spans: spans.args.clone().into(), ..self }
+    }
+
+    // The span of the first argument subterm for which `found` holds, if it was read from the source
+    pub fn find_span(&self, args: &[Term], found: impl Fn(&Term) -> bool) -> Option<Span> {
+        args.iter().zip(self.arg_spans.iter()).find_map(|(arg, span)| span.find(arg, &found))
+    }
+
+    // The goal a term stands for when it is called; variables are not callable
+    pub fn from_term(term: &Term) -> Option<Atom> {
+        match term {
+            Term::Constant(name) => Some(Atom::new(name.clone(), vec![])),
+            Term::Compound { name, args } => Some(Atom::new(name.clone(), args.clone())),
+            Term::Variable(_) | Term::Number(_) | Term::String(_) => None,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -16,6 +211,50 @@ pub enum Statement { // Clause
     Fact(Atom),
     Rule { head: Atom, body: Vec<Atom> },
     Query { body: Vec<Atom> },
+    Directive { body: Vec<Atom> }, // ":- Goal." run while the program is loaded
+}
+
+// A region of the source: the char offsets [start, end) and the 1-based line and column of `start`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    // The span from the start of `self` to the end of `other`
+    pub fn to(self, other: Span) -> Span {
+        Span { end: other.end, ..self }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
+}
+
+// Lets token streams be compared with plain tokens regardless of where they were read
+impl PartialEq<Token> for SpannedToken {
+    fn eq(&self, other: &Token) -> bool {
+        self.token == *other
+    }
+}
+
+impl Statement {
+    // The span from the first to the last atom of the statement
+    pub fn span(&self) -> Span {
+        match self {
+            Statement::Fact(head) => head.span,
+            Statement::Rule { head, body } => head.span.to(body.last().map_or(head.span, |atom| atom.span)),
+            Statement::Query { body } | Statement::Directive { body } => match (body.first(), body.last()) {
+                (Some(first), Some(last)) => first.span.to(last.span),
+                _ => Span::default(),
+            },
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -28,8 +267,45 @@ pub enum Token {
     Period,
     RuleArrow,
     QueryOperator,
+    Cut,
+    NotOperator,
+    Semicolon,
+    IfArrow,
+    LBracket,
+    RBracket,
+    Bar,
+    Number(Number),
+    String(String), // the text of a double-quoted string, with escapes resolved
+    Operator(String), // symbolic operator such as + or =<
 }
 
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let symbol = match self {
+            Token::Identifier(s) | Token::Variable(s) | Token::Operator(s) => s,
+            Token::Number(n) => return write!(f, "{}", n),
+            Token::String(s) => return write!(f, "\"{}\"", s),
+            Token::LParen => "(",
+            Token::RParen => ")",
+            Token::Comma => ",",
+            Token::Period => ".",
+            Token::RuleArrow => ":-",
+            Token::QueryOperator => "?-",
+            Token::Cut => "!",
+            Token::NotOperator => "\\+",
+            Token::Semicolon => ";",
+            Token::IfArrow => "->",
+            Token::LBracket => "[",
+            Token::RBracket => "]",
+            Token::Bar => "|",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+// Characters that make up symbolic atoms such as + or =<; a maximal run of them forms one token
+pub(crate) const SYMBOL_CHARS: &str = "+-*/\\^<>=:.?@~&$";
+
 #[derive(Debug, Clone)]
 pub struct Rule {
     pub head: Atom,
@@ -42,60 +318,347 @@ pub struct Database {
     pub rules: Vec<Rule>,
 }
 
-pub fn tokenize(input: &str) -> Result<Vec<Token>, String> {
-    let mut tokens = Vec::new();
+pub fn tokenize(input: &str) -> Result<Vec<SpannedToken>, PrologError> {
+    tokenize_with_comments(input).map(|(tokens, _)| tokens)
+}
+
+// A %! or %% documentation comment, without its marker
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Comment {
+    pub text: String,
+    pub span: Span,
+}
+
+// Tokenizes `input` and also returns its documentation comments; other comments are skipped.
+pub fn tokenize_with_comments(input: &str) -> Result<(Vec<SpannedToken>, Vec<Comment>), PrologError> {
+    let (tokens, comments, errors) = tokenize_program(input);
+    match errors.into_iter().next() {
+        Some(error) => Err(error),
+        None => Ok((tokens, comments)),
+    }
+}
+
+// Tokenizes a whole program like `tokenize_with_comments`, but carries on after a lexical error: the tokens
+// of the clause it is in are dropped and tokenizing starts again after the end of that clause.
+pub fn tokenize_program(input: &str) -> (Vec<SpannedToken>, Vec<Comment>, Vec<PrologError>) {
+    let mut tokens: Vec<SpannedToken> = Vec::new();
+    let mut comments = Vec::new();
+    let mut errors = Vec::new();
     let chars: Vec<char> = input.chars().collect();
+    let lines = LineIndex::new(&chars);
+    let error = |message: String, start: usize, end: usize| {
+        PrologError::Lexical(SourceError::new(message, lines.span(start, end), Some(input)))
+    };
     let mut i = 0;
 
-    while i < chars.len() {
+    'chars: while i < chars.len() {
         let c = chars[i];
-        if c.is_whitespace() {
-            i += 1;
-        } else if c.is_lowercase() {
-            let mut s = c.to_string();
-            i += 1;
-            while i < chars.len() && chars[i].is_alphanumeric() {
-                s.push(chars[i]);
+        let start = i;
+        let token = 'token: {
+            Ok(if c.is_whitespace() {
                 i += 1;
-            }
-            tokens.push(Token::Identifier(s));
-        } else if c.is_uppercase() {
-            let mut s = c.to_string();
-            i += 1;
-            while i < chars.len() && chars[i].is_alphanumeric() {
-                s.push(chars[i]);
-                i += 1;
-            }
-            tokens.push(Token::Variable(s));
-        } else {
-            match c {
-                '(' => { tokens.push(Token::LParen); i += 1; },
-                ')' => { tokens.push(Token::RParen); i += 1; },
-                ',' => { tokens.push(Token::Comma); i += 1; },
-                '.' => { tokens.push(Token::Period); i += 1; },
-                ':' => {
-                    if i + 1 < chars.len() && chars[i+1] == '-' {
-                        tokens.push(Token::RuleArrow);
-                        i += 2;
-                    } else { return Err("Unexpected ':'".to_string()) }
+                continue 'chars;
+            } else if c == '%' {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+                if start + 1 < chars.len() && matches!(chars[start + 1], '!' | '%') {
+                    let text: String = chars[start + 2..i].iter().collect();
+                    comments.push(Comment { text: text.trim().to_string(), span: lines.span(start, i) });
                 }
-                '?' => {
-                    if i + 1 < chars.len() && chars[i + 1] == '-' {
-                        tokens.push(Token::QueryOperator);
+                continue 'chars;
+            } else if c == '/' && chars.get(i + 1) == Some(&'*') {
+                // Block comments may be nested, so commenting out code that has comments of its own works
+                let mut depth = 0;
+                while i < chars.len() {
+                    if starts_block_comment(&chars, i) {
+                        depth += 1;
                         i += 2;
+                    } else if chars[i] == '*' && chars.get(i + 1) == Some(&'/') {
+                        depth -= 1;
+                        i += 2;
+                        if depth == 0 {
+                            break;
+                        }
                     } else {
-                        return Err("Unexpected '?'".to_string())
+                        i += 1;
+                    }
+                }
+                if depth > 0 {
+                    break 'token Err(error("Unterminated block comment".to_string(), start, start + 2));
+                }
+                continue 'chars;
+            } else if c.is_lowercase() {
+                let mut s = c.to_string();
+                i += 1;
+                while i < chars.len() && is_name_char(chars[i]) {
+                    s.push(chars[i]);
+                    i += 1;
+                }
+                Token::Identifier(s)
+            } else if c.is_uppercase() || c == '_' {
+                let mut s = c.to_string();
+                i += 1;
+                while i < chars.len() && is_name_char(chars[i]) {
+                    s.push(chars[i]);
+                    i += 1;
+                }
+                Token::Variable(s)
+            } else if c == '\'' || c == '"' {
+                let (text, next) = match tokenize_quoted(&chars, i) {
+                    Ok(quoted) => quoted,
+                    Err((message, end)) => {
+                        i = end;
+                        break 'token Err(error(message, start, end));
+                    }
+                };
+                i = next;
+                if c == '\'' { Token::Identifier(text) } else { Token::String(text) }
+            } else if c.is_ascii_digit() {
+                let (number, next) = match tokenize_number(&chars, i) {
+                    Ok(number) => number,
+                    Err((message, end)) => {
+                        i = end;
+                        break 'token Err(error(message, start, end));
+                    }
+                };
+                i = next;
+                Token::Number(number)
+            } else if SYMBOL_CHARS.contains(c) {
+                // A /* starts a comment even right after a symbol, as in g./*c*/
+                while i < chars.len() && SYMBOL_CHARS.contains(chars[i]) && !starts_block_comment(&chars, i) {
+                    i += 1;
+                }
+                let symbol: String = chars[start..i].iter().collect();
+                // A '.' before layout ends the clause, so it cannot be the last char of an atom such as '+.',
+                // unless the atom is a standard operator like =..
+                let ends_clause = symbol.ends_with('.') && is_layout_or_end(&chars, i);
+                match symbol.as_str() {
+                    "." => Token::Period,
+                    ":-" => Token::RuleArrow,
+                    "?-" => Token::QueryOperator,
+                    "\\+" => Token::NotOperator,
+                    "->" => Token::IfArrow,
+                    _ if ends_clause && !Operators::standard().is_operator(&symbol) => {
+                        break 'token Err(error(format!("Unexpected '{}'", symbol), start, i))
                     }
+                    _ => Token::Operator(symbol),
+                }
+            } else {
+                i += 1;
+                match c {
+                    '(' => Token::LParen,
+                    ')' => Token::RParen,
+                    ',' => Token::Comma,
+                    '!' => Token::Cut,
+                    ';' => Token::Semicolon,
+                    '[' => Token::LBracket,
+                    ']' => Token::RBracket,
+                    '|' => Token::Bar,
+                    _ => break 'token Err(error(format!("Unknown char '{}'", c), start, i)),
+                }
+            })
+        };
+        match token {
+            Ok(token) => tokens.push(SpannedToken { token, span: lines.span(start, i) }),
+            Err(e) => {
+                errors.push(e);
+                let clause_start = tokens.iter().rposition(|t| t.token == Token::Period).map_or(0, |end| end + 1);
+                tokens.truncate(clause_start);
+                // unless the bad token was the end of the clause itself
+                if !ends_clause(&chars, i) {
+                    i = (i..chars.len()).find(|&end| ends_clause(&chars, end + 1)).map_or(chars.len(), |end| end + 1);
                 }
-                _ => return Err(format!("Unknown char '{}' at position {}", c, i))
+            }
+        }
+    }
+
+    (tokens, comments, errors)
+}
+
+// True if the chars before `i` end a clause: a '.' that is not part of a symbol atom, followed by layout
+fn ends_clause(chars: &[char], i: usize) -> bool {
+    i > 0
+        && chars[i - 1] == '.'
+        && (i < 2 || !SYMBOL_CHARS.contains(chars[i - 2]))
+        && is_layout_or_end(chars, i)
+}
+
+// Whitespace, a comment or the end of the input at position `i`, which may follow the '.' that ends a clause
+fn is_layout_or_end(chars: &[char], i: usize) -> bool {
+    chars.get(i).is_none_or(|&c| c.is_whitespace() || c == '%') || starts_block_comment(chars, i)
+}
+
+fn starts_block_comment(chars: &[char], i: usize) -> bool {
+    chars.get(i) == Some(&'/') && chars.get(i + 1) == Some(&'*')
+}
 
+// Letters, digits and underscores continue an identifier or a variable name
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+// Reads a quoted atom or string starting with the quote at `start` and returns its text and the position
+// after the closing quote. The quote is written inside by doubling it ('it''s') or escaping it, and
+// backslash starts an ISO escape sequence: \n \t \r \a \b \f \v \0 \\ \' \" \`, \xHEX\ and \OCTAL\
+// character codes, and a backslash before a newline continues the text on the next line.
+// On error returns the message and the position where the offending part ends.
+fn tokenize_quoted(chars: &[char], start: usize) -> Result<(String, usize), (String, usize)> {
+    let quote = chars[start];
+    let mut text = String::new();
+    let mut i = start + 1;
+    loop {
+        match chars.get(i) {
+            None => {
+                let kind = if quote == '\'' { "quoted atom" } else { "string" };
+                return Err((format!("Unterminated {}", kind), chars.len()));
+            }
+            Some(&c) if c == quote => {
+                if chars.get(i + 1) != Some(&quote) {
+                    return Ok((text, i + 1));
+                }
+                text.push(quote);
+                i += 2;
+            }
+            Some('\\') => {
+                let (c, next) = tokenize_escape(chars, i + 1)?;
+                text.extend(c);
+                i = next;
+            }
+            Some(&c) => {
+                text.push(c);
+                i += 1;
             }
         }
     }
+}
 
-    Ok(tokens)
+// Reads the escape sequence after a backslash at `start - 1` and returns the character it stands for
+// (none for a line continuation) and the position after it
+fn tokenize_escape(chars: &[char], start: usize) -> Result<(Option<char>, usize), (String, usize)> {
+    let escape = *chars.get(start).ok_or(("Unterminated escape sequence".to_string(), start))?;
+    let c = match escape {
+        'n' => '\n',
+        't' => '\t',
+        'r' => '\r',
+        'a' => '\u{7}',
+        'b' => '\u{8}',
+        'f' => '\u{c}',
+        'v' => '\u{b}',
+        'e' => '\u{1b}',
+        's' => ' ',
+        '\\' | '\'' | '"' | '`' => escape,
+        '\n' => return Ok((None, start + 1)),
+        'x' | '0'..='7' => {
+            let (radix, from) = if escape == 'x' { (16, start + 1) } else { (8, start) };
+            let mut end = from;
+            while end < chars.len() && chars[end].is_digit(radix) {
+                end += 1;
+            }
+            let digits: String = chars[from..end].iter().collect();
+            let code = u32::from_str_radix(&digits, radix).ok().and_then(char::from_u32);
+            // the closing backslash is optional
+            let end = if chars.get(end) == Some(&'\\') { end + 1 } else { end };
+            let c = code.ok_or(("Invalid character code in escape sequence".to_string(), end))?;
+            return Ok((Some(c), end));
+        }
+        _ => return Err((format!("Undefined escape sequence '\\{}'", escape), start + 1)),
+    };
+    Ok((Some(c), start + 1))
 }
 
+// Start offsets of the lines of the source, to turn char offsets into line and column numbers
+struct LineIndex {
+    starts: Vec<usize>,
+}
+
+impl LineIndex {
+    fn new(chars: &[char]) -> Self {
+        let newlines = chars.iter().enumerate().filter(|(_, &c)| c == '\n').map(|(i, _)| i + 1);
+        LineIndex { starts: std::iter::once(0).chain(newlines).collect() }
+    }
+
+    fn span(&self, start: usize, end: usize) -> Span {
+        let line = self.starts.partition_point(|&line_start| line_start <= start);
+        Span { start, end, line, column: start - self.starts[line - 1] + 1 }
+    }
+}
+
+// Reads a numeric literal starting at `start` and returns it with the position after it:
+// decimal integers of any size, rationals (1r3), floats (1.5, 2.0e-3), character codes (0'a)
+// and 0x, 0o, 0b radix integers.
+// On error returns the message and the position where the offending literal ends.
+fn tokenize_number(chars: &[char], start: usize) -> Result<(Number, usize), (String, usize)> {
+    let digits_from = |from: usize, radix: u32| {
+        let mut end = from;
+        while end < chars.len() && chars[end].is_digit(radix) {
+            end += 1;
+        }
+        end
+    };
+    let value = |from: usize, to: usize, radix: u32| {
+        let digits: String = chars[from..to].iter().collect();
+        BigInt::parse_bytes(digits.as_bytes(), radix).unwrap()
+    };
+
+    if chars[start] == '0' && start + 1 < chars.len() {
+        let radix = match chars[start + 1] {
+            'x' => Some(16),
+            'o' => Some(8),
+            'b' => Some(2),
+            _ => None,
+        };
+        if let Some(radix) = radix {
+            let end = digits_from(start + 2, radix);
+            if end > start + 2 {
+                return Ok((Number::from_bigint(value(start + 2, end, radix)), end));
+            }
+        }
+        if chars[start + 1] == '\'' && start + 2 < chars.len() {
+            // 0'\n is the code of an escaped character and 0''' the code of the quote itself
+            return match chars[start + 2] {
+                '\\' => match tokenize_escape(chars, start + 3)? {
+                    (Some(c), end) => Ok((Number::Integer(c as i64), end)),
+                    (None, end) => Err(("Expected character after 0'".to_string(), end)),
+                },
+                '\'' if chars.get(start + 3) == Some(&'\'') => Ok((Number::Integer('\'' as i64), start + 4)),
+                c => Ok((Number::Integer(c as i64), start + 3)),
+            };
+        }
+    }
+
+    let mut end = digits_from(start, 10);
+
+    // A rational literal such as 1r3
+    if end + 1 < chars.len() && chars[end] == 'r' && chars[end + 1].is_ascii_digit() {
+        let denominator_end = digits_from(end + 1, 10);
+        let (numerator, denominator) = (value(start, end, 10), value(end + 1, denominator_end, 10));
+        if denominator.is_zero() {
+            return Err(("Zero denominator in rational".to_string(), denominator_end));
+        }
+        return Ok((Number::from_rational(BigRational::new(numerator, denominator)), denominator_end));
+    }
+
+    // A '.' only continues the number when a digit follows, otherwise it ends the clause
+    if end + 1 < chars.len() && chars[end] == '.' && chars[end + 1].is_ascii_digit() {
+        end = digits_from(end + 1, 10);
+        if end < chars.len() && (chars[end] == 'e' || chars[end] == 'E') {
+            let mut exponent = end + 1;
+            if exponent < chars.len() && (chars[exponent] == '+' || chars[exponent] == '-') {
+                exponent += 1;
+            }
+            if exponent < chars.len() && chars[exponent].is_ascii_digit() {
+                end = digits_from(exponent, 10);
+            }
+        }
+        let literal: String = chars[start..end].iter().collect();
+        return literal.parse::<f64>()
+            .map(|f| (Number::Float(f), end))
+            .map_err(|_| (format!("Invalid float '{}'", literal), end));
+    }
+
+    Ok((Number::from_bigint(value(start, end, 10)), end))
+}
 
 // Tests
 #[cfg(test)]
@@ -151,6 +714,16 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_tokenize_anonymous_variables() {
+        assert_eq!(tokenize("_ _X _1 X_").unwrap(), vec![
+            Token::Variable("_".to_string()),
+            Token::Variable("_X".to_string()),
+            Token::Variable("_1".to_string()),
+            Token::Variable("X_".to_string()),
+        ]);
+    }
+
     #[test]
     fn test_tokenize_symbols() {
         let tokens = tokenize("(),.");
@@ -194,6 +767,232 @@ mod tests {
 
     }
 
+    #[test]
+    fn test_tokenize_cut() {
+        let tokens = tokenize("p :- q, !.");
+        match tokens {
+            Ok(tokens) => {
+                assert_eq!(tokens, vec![
+                    Token::Identifier("p".to_string()),
+                    Token::RuleArrow,
+                    Token::Identifier("q".to_string()),
+                    Token::Comma,
+                    Token::Cut,
+                    Token::Period
+                ]);
+            },
+            Err(err) => {
+                panic!("{:?}", err);
+            }
+        }
+    }
+
+    #[test]
+    fn test_tokenize_not_operator() {
+        let tokens = tokenize("\\+ p");
+        match tokens {
+            Ok(tokens) => {
+                assert_eq!(tokens, vec![Token::NotOperator, Token::Identifier("p".to_string())]);
+            },
+            Err(err) => {
+                panic!("{:?}", err);
+            }
+        }
+    }
+
+    #[test]
+    fn test_tokenize_control_constructs() {
+        let tokens = tokenize("(a -> b ; c)");
+        match tokens {
+            Ok(tokens) => {
+                assert_eq!(tokens, vec![
+                    Token::LParen,
+                    Token::Identifier("a".to_string()),
+                    Token::IfArrow,
+                    Token::Identifier("b".to_string()),
+                    Token::Semicolon,
+                    Token::Identifier("c".to_string()),
+                    Token::RParen
+                ]);
+            },
+            Err(err) => {
+                panic!("{:?}", err);
+            }
+        }
+    }
+
+    #[test]
+    fn test_token_spans() {
+        let tokens = tokenize("p(ab).\n  q :- 12.").unwrap();
+        let spans: Vec<(usize, usize, usize, usize)> =
+            tokens.iter().map(|t| (t.span.line, t.span.column, t.span.start, t.span.end)).collect();
+        assert_eq!(spans, vec![
+            (1, 1, 0, 1),
+            (1, 2, 1, 2),
+            (1, 3, 2, 4),
+            (1, 5, 4, 5),
+            (1, 6, 5, 6),
+            (2, 3, 9, 10),
+            (2, 5, 11, 13),
+            (2, 8, 14, 16),
+            (2, 10, 16, 17),
+        ]);
+    }
+
+    #[test]
+    fn test_tokenize_error_excerpt() {
+        let error = |src: &str| tokenize(src).unwrap_err().to_string();
+        assert_eq!(error("p(X).\nq(#)."), "Lexical error: 2:3: Unknown char '#'\nq(#).\n  ^");
+        assert_eq!(error("x :- 1r0."), "Lexical error: 1:6: Zero denominator in rational\nx :- 1r0.\n     ^^^");
+        assert!(matches!(tokenize("a ?."), Err(PrologError::Lexical(e)) if e.span.column == 3));
+    }
+
+    #[test]
+    fn test_tokenize_comments() {
+        let tokens = tokenize("p. % a comment\n/* a block /* nested */ still a comment */ q. 4 /*x*/ / 2").unwrap();
+        assert_eq!(tokens, vec![
+            Token::Identifier("p".to_string()),
+            Token::Period,
+            Token::Identifier("q".to_string()),
+            Token::Period,
+            Token::Number(Number::Integer(4)),
+            Token::Operator("/".to_string()),
+            Token::Number(Number::Integer(2)),
+        ]);
+        assert_eq!(tokenize("p. % only a comment at the end").unwrap().len(), 2);
+        assert_eq!(tokenize("/* a /* b */ c */ d.").unwrap(), vec![Token::Identifier("d".to_string()), Token::Period]);
+        assert_eq!(tokenize("p. /* /* /**/ */ */ q.").unwrap().len(), 4);
+        assert!(matches!(tokenize("p. /* /* */ q."), Err(PrologError::Lexical(e)) if e.span.column == 4));
+        assert_eq!(tokenize("g./*c*/ h.").unwrap(), vec![
+            Token::Identifier("g".to_string()),
+            Token::Period,
+            Token::Identifier("h".to_string()),
+            Token::Period,
+        ]);
+        assert_eq!(tokenize("X = - /*c*/ 1").unwrap()[2], Token::Operator("-".to_string()));
+        assert!(matches!(tokenize("p. /* x"), Err(PrologError::Lexical(e)) if e.span.column == 4));
+    }
+
+    #[test]
+    fn test_tokenize_doc_comments() {
+        let (tokens, comments) = tokenize_with_comments("%! p(+X)\n%  not a doc comment\n%% Holds for a.\np(a).").unwrap();
+        assert_eq!(tokens.len(), 5);
+        let texts: Vec<&str> = comments.iter().map(|c| c.text.as_str()).collect();
+        assert_eq!(texts, vec!["p(+X)", "Holds for a."]);
+        assert_eq!((comments[1].span.line, comments[1].span.column), (3, 1));
+    }
+
+    #[test]
+    fn test_tokenize_quoted() {
+        let tokens = tokenize(r#"'New York' 'it''s' 'a\nb\tc\\' '\x41\\101\' "say ""hi""" my_atom My_Var"#).unwrap();
+        assert_eq!(tokens, vec![
+            Token::Identifier("New York".to_string()),
+            Token::Identifier("it's".to_string()),
+            Token::Identifier("a\nb\tc\\".to_string()),
+            Token::Identifier("AA".to_string()),
+            Token::String("say \"hi\"".to_string()),
+            Token::Identifier("my_atom".to_string()),
+            Token::Variable("My_Var".to_string()),
+        ]);
+        assert_eq!(tokenize("'a\\\nb' 0'\\n 0''' 0'a").unwrap(), vec![
+            Token::Identifier("ab".to_string()),
+            Token::Number(Number::Integer(10)),
+            Token::Number(Number::Integer(39)),
+            Token::Number(Number::Integer(97)),
+        ]);
+
+        let error = |src: &str| tokenize(src).unwrap_err().to_string();
+        assert_eq!(error("p('abc)."), "Lexical error: 1:3: Unterminated quoted atom\np('abc).\n  ^^^^^^");
+        assert_eq!(error("\"abc"), "Lexical error: 1:1: Unterminated string\n\"abc\n^^^^");
+        assert_eq!(error("'\\q'"), "Lexical error: 1:1: Undefined escape sequence '\\q'\n'\\q'\n^^^");
+    }
+
+    #[test]
+    fn test_tokenize_lists() {
+        let tokens = tokenize("[a, B | []]").unwrap();
+        assert_eq!(tokens, vec![
+            Token::LBracket,
+            Token::Identifier("a".to_string()),
+            Token::Comma,
+            Token::Variable("B".to_string()),
+            Token::Bar,
+            Token::LBracket,
+            Token::RBracket,
+            Token::RBracket
+        ]);
+    }
+
+    #[test]
+    fn test_list_terms() {
+        let a = Term::Constant("a".to_string());
+        let t = Term::Variable("T".to_string());
+        let list = Term::list(vec![a.clone(), a.clone()], t.clone());
+        assert_eq!(list, Term::cons(a.clone(), Term::cons(a.clone(), t.clone())));
+        assert_eq!(list.list_items(), (vec![&a, &a], &t));
+        assert_eq!(Term::list(vec![], Term::nil()), Term::Constant("[]".to_string()));
+    }
+
+    #[test]
+    fn test_tokenize_numbers() {
+        let tokens = tokenize("42 2.75 1.0e3 2.5E-1 0'a 0xff 0b101 7.");
+        match tokens {
+            Ok(tokens) => {
+                assert_eq!(tokens, vec![
+                    Token::Number(Number::Integer(42)),
+                    Token::Number(Number::Float(2.75)),
+                    Token::Number(Number::Float(1000.0)),
+                    Token::Number(Number::Float(0.25)),
+                    Token::Number(Number::Integer(97)),
+                    Token::Number(Number::Integer(255)),
+                    Token::Number(Number::Integer(5)),
+                    Token::Number(Number::Integer(7)),
+                    Token::Period
+                ]);
+            },
+            Err(err) => {
+                panic!("{:?}", err);
+            }
+        }
+    }
+
+    #[test]
+    fn test_tokenize_operators() {
+        let tokens = tokenize("X is Y*2 - 1, X =\\= Y, X >= 0");
+        match tokens {
+            Ok(tokens) => {
+                assert_eq!(tokens, vec![
+                    Token::Variable("X".to_string()),
+                    Token::Identifier("is".to_string()),
+                    Token::Variable("Y".to_string()),
+                    Token::Operator("*".to_string()),
+                    Token::Number(Number::Integer(2)),
+                    Token::Operator("-".to_string()),
+                    Token::Number(Number::Integer(1)),
+                    Token::Comma,
+                    Token::Variable("X".to_string()),
+                    Token::Operator("=\\=".to_string()),
+                    Token::Variable("Y".to_string()),
+                    Token::Comma,
+                    Token::Variable("X".to_string()),
+                    Token::Operator(">=".to_string()),
+                    Token::Number(Number::Integer(0))
+                ]);
+            },
+            Err(err) => {
+                panic!("{:?}", err);
+            }
+        }
+        assert_eq!(
+            tokenize("99999999999999999999 1r3 4r2").unwrap(),
+            vec![
+                Token::Number(Number::BigInt("99999999999999999999".parse().unwrap())),
+                Token::Number(Number::Rational(BigRational::new(1.into(), 3.into()))),
+                Token::Number(Number::Integer(2)),
+            ]
+        );
+        assert!(tokenize("1r0").is_err());
+    }
+
     #[test]
     //#[should_panic(expected = "Unexpected '?'")]
     fn test_tokenize_unexpected_question() {
@@ -203,7 +1002,7 @@ mod tests {
             Ok(tokens) => {
                 panic!("Invalid tokens: {:?}", tokens);
             }
-            Err(err) => {
+            Err(_err) => {
                 // good
             }
         }
@@ -217,7 +1016,7 @@ mod tests {
             Ok(tokens) => {
                 panic!("Invalid tokens: {:?}", tokens);
             }
-            Err(err) => {
+            Err(_err) => {
                 // good
             }
         }
@@ -232,7 +1031,7 @@ mod tests {
             Ok(tokens) => {
                 panic!("Invalid tokens: {:?}", tokens);
             }
-            Err(err) => {
+            Err(_err) => {
                 // good
             }
         }
@@ -1,7 +1,22 @@
-use crate::tokenizer::{Term, Atom};
+use crate::tokenizer::{Term, Atom, Number, SYMBOL_CHARS};
+use crate::arithmetic::compare_numbers;
+use crate::operators::Operators;
 
+use std::cmp::Ordering;
 use std::collections::HashMap;
-pub(crate) type Substitution = HashMap<String, Term>;
+
+// A persistent map, so that a choicepoint keeps the bindings of its time without copying them
+pub(crate) type Substitution = im_rc::HashMap<String, Term>;
+
+// The name of the n-th variable made up by the solver, e.g. X#3 for a renamed clause variable X or _#4 for an
+// anonymous one. '#' cannot be part of a variable name in the source, so these never clash with a user's names.
+pub fn fresh_name(base: &str, n: usize) -> String {
+    format!("{}#{}", base, n)
+}
+
+pub fn is_fresh_name(name: &str) -> bool {
+    name.contains('#')
+}
 
 pub fn print_substitution(subs: &Substitution) -> String {
     let pairs: Vec<String> = subs
@@ -11,23 +26,177 @@ pub fn print_substitution(subs: &Substitution) -> String {
     format!("{{{}}}", pairs.join(", "))
 }
 
-fn format_term(term: &Term) -> String { // helper
+pub fn format_term(term: &Term) -> String {
+    format_term_with(term, Operators::standard())
+}
+
+// Writes a term with its operators in operator notation, e.g. a:-b or X is Y+1, adding parentheses where
+// the priorities of `operators` require them
+pub fn format_term_with(term: &Term, operators: &Operators) -> String {
+    write_term(term, operators, 1200)
+}
+
+// Writes `term` as an operand of priority at most `max_priority`
+fn write_term(term: &Term, operators: &Operators, max_priority: u16) -> String {
+    let (text, priority) = match term {
+        Term::Variable(v) => (v.clone(), 0),
+        Term::Constant(c) => (format_atom(c), 0),
+        Term::Number(n) => (n.to_string(), 0),
+        Term::String(s) => (quote(s, '"'), 0),
+        Term::Compound { name, args } if name == "." && args.len() == 2 => (format_list(term, operators), 0),
+        Term::Compound { name, args } => match (args.as_slice(), operators.infix(name), operators.prefix(name), operators.postfix(name)) {
+            ([left, right], Some((priority, op_type)), _, _) => {
+                let (left_max, right_max) = op_type.argument_priorities(priority);
+                let left = write_term(left, operators, left_max);
+                let right = write_term(right, operators, right_max);
+                let text = match name.as_str() {
+                    "," => format!("{},{}", left, right),
+                    _ if is_alphanumeric(name) => format!("{} {} {}", left, format_atom(name), right),
+                    _ => join_symbolic(&join_symbolic(&left, &format_atom(name)), &right),
+                };
+                (text, priority)
+            }
+            // a negative number or a parenthesised operand would read back differently after a prefix operator
+            ([operand], _, Some((priority, op_type)), _) if !matches!(operand, Term::Number(_)) => {
+                let arg = write_term(operand, operators, op_type.argument_priorities(priority).1);
+                if arg.starts_with('(') {
+                    (format!("{}({})", format_atom(name), write_term(operand, operators, 999)), 0)
+                } else if is_alphanumeric(name) {
+                    (format!("{} {}", format_atom(name), arg), priority)
+                } else {
+                    (join_symbolic(&format_atom(name), &arg), priority)
+                }
+            }
+            ([arg], _, _, Some((priority, op_type))) => {
+                let arg = write_term(arg, operators, op_type.argument_priorities(priority).0);
+                (join_symbolic(&arg, &format_atom(name)), priority)
+            }
+            _ => {
+                let args_str: Vec<String> = args.iter().map(|arg| write_term(arg, operators, 999)).collect();
+                (format!("{}({})", format_atom(name), args_str.join(", ")), 0)
+            }
+        },
+    };
+    if priority > max_priority { format!("({})", text) } else { text }
+}
+
+fn is_alphanumeric(name: &str) -> bool {
+    name.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+// Concatenates two pieces of a term, with a space between them if they would otherwise read as one symbol,
+// e.g. 1- -1 rather than 1--1
+fn join_symbolic(left: &str, right: &str) -> String {
+    let symbolic = |c: Option<char>| c.is_some_and(|c| SYMBOL_CHARS.contains(c));
+    if symbolic(left.chars().last()) && symbolic(right.chars().next()) {
+        format!("{} {}", left, right)
+    } else {
+        format!("{}{}", left, right)
+    }
+}
+
+// Writes an atom so that it reads back as the same atom, quoting it when needed, e.g. 'New York' or 'X'
+fn format_atom(name: &str) -> String {
+    let mut chars = name.chars();
+    let plain = match chars.next() {
+        Some(first) if first.is_lowercase() => chars.all(|c| c.is_alphanumeric() || c == '_'),
+        Some(_) if matches!(name, "[]" | "!" | ";" | "{}") => true,
+        Some(_) => name != "." && name.chars().all(|c| SYMBOL_CHARS.contains(c)),
+        None => false,
+    };
+    if plain { name.to_string() } else { quote(name, '\'') }
+}
+
+// Encloses text in quotes, escaping the quote, backslashes and control characters
+fn quote(text: &str, quote: char) -> String {
+    let mut quoted = quote.to_string();
+    for c in text.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c if c == quote => quoted.extend(['\\', c]),
+            c if c.is_control() => quoted.push_str(&format!("\\x{:x}\\", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push(quote);
+    quoted
+}
+
+// Formats a list in bracket notation, e.g. [1,2,3] or [a|T] for a partial list
+fn format_list(list: &Term, operators: &Operators) -> String {
+    let (items, tail) = list.list_items();
+    let items: Vec<String> = items.into_iter().map(|item| write_term(item, operators, 999)).collect();
+    match tail {
+        Term::Constant(nil) if nil == "[]" => format!("[{}]", items.join(",")),
+        _ => format!("[{}|{}]", items.join(","), write_term(tail, operators, 999)),
+    }
+}
+
+// Formats an answer the way a Prolog top level does, e.g. "X = john, Y = mary", or "true" with no bindings
+pub fn format_answer(subs: &Substitution) -> String {
+    format_answer_with(subs, Operators::standard())
+}
+
+// Formats an answer with the operators of the program, which may declare its own. Variables starting with
+// '_' and variables left unbound are not shown; a variable made up by the solver is shown by the name of the
+// query variable bound to it, or else as _G1, _G2, ... in the order it appears.
+pub fn format_answer_with(subs: &Substitution, operators: &Operators) -> String {
+    let mut vars: Vec<&String> = subs.keys().filter(|var| !var.starts_with('_')).collect();
+    vars.sort();
+    let mut names: HashMap<String, String> = HashMap::new();
+    vars.retain(|var| match &subs[*var] {
+        Term::Variable(v) if v == *var => false,
+        Term::Variable(v) if is_fresh_name(v) && !names.contains_key(v) => {
+            names.insert(v.clone(), var.to_string());
+            false
+        }
+        _ => true,
+    });
+    if vars.is_empty() {
+        return "true".to_string();
+    }
+    // the values are right operands of =
+    vars.iter()
+        .map(|var| format!("{} = {}", var, write_term(&rename_fresh(&subs[*var], &mut names), operators, 699)))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// Gives the variables made up by the solver in `term` the names in `names`, adding _G1, _G2, ... for new ones
+fn rename_fresh(term: &Term, names: &mut HashMap<String, String>) -> Term {
     match term {
-        Term::Variable(v) => v.clone(),
-        Term::Constant(c) => c.clone(),
-        Term::Compound { name, args } => {
-            let args_str: Vec<String> = args.iter().map(|t| format_term(t)).collect();
-            format!("{}({})", name, args_str.join(", "))
+        Term::Variable(v) if is_fresh_name(v) => {
+            let n = names.values().filter(|name| name.starts_with("_G")).count();
+            Term::Variable(names.entry(v.clone()).or_insert_with(|| format!("_G{}", n + 1)).clone())
         }
+        Term::Compound { name, args } => Term::Compound {
+            name: name.clone(),
+            args: args.iter().map(|arg| rename_fresh(arg, names)).collect(),
+        },
+        _ => term.clone(),
     }
 }
 
 pub fn unify_terms(t1: &Term, t2: &Term, subs: &mut Substitution) -> bool {
+    // Dereference bound variables first so that X = X is recognised even through a chain of bindings
+    if let Term::Variable(v) = t1 {
+        if let Some(bound) = subs.get(v).cloned() {
+            return unify_terms(&bound, t2, subs);
+        }
+    }
+    if let Term::Variable(v) = t2 {
+        if let Some(bound) = subs.get(v).cloned() {
+            return unify_terms(t1, &bound, subs);
+        }
+    }
+
     match (t1, t2) {
+        (Term::Variable(v1), Term::Variable(v2)) if v1 == v2 => true,
+
         (Term::Variable(v), t) | (t, Term::Variable(v)) => {
-            if let Some(bound) = subs.get(v).cloned() {
-                unify_terms(&bound, t, subs)
-            } else if occurs_check(v, t, subs) {
+            if occurs_check(v, t, subs) {
                 false
             } else {
                 subs.insert(v.clone(), (*t).clone());
@@ -37,6 +206,10 @@ pub fn unify_terms(t1: &Term, t2: &Term, subs: &mut Substitution) -> bool {
 
         (Term::Constant(c1), Term::Constant(c2)) => c1 == c2,
 
+        (Term::Number(n1), Term::Number(n2)) => n1 == n2,
+
+        (Term::String(s1), Term::String(s2)) => s1 == s2,
+
         (Term::Compound { name: n1, args: a1 },
             Term::Compound { name: n2, args: a2 },) => {
             if n1 != n2 || a1.len() != a2.len() {
@@ -61,23 +234,57 @@ fn occurs_check(var: &str, term: &Term, subs: &Substitution) -> bool {
     }
 }
 
-pub fn unify_atoms(a1: &Atom, a2: &Atom) -> Option<Substitution> {
-    if a1.name != a2.name || a1.args.len() != a2.args.len() {
-        return None;
+// Compares two terms (with their bindings applied) in the standard order of terms:
+// Var < Number < Atom < String < Compound. Numbers compare by value, with a float before an equal integer;
+// compound terms compare by arity, then name, then arguments from left to right.
+pub fn compare_terms(t1: &Term, t2: &Term) -> Ordering {
+    fn rank(term: &Term) -> u8 {
+        match term {
+            Term::Variable(_) => 0,
+            Term::Number(_) => 1,
+            Term::Constant(_) => 2,
+            Term::String(_) => 3,
+            Term::Compound { .. } => 4,
+        }
     }
+
+    match (t1, t2) {
+        (Term::Variable(v1), Term::Variable(v2)) => v1.cmp(v2),
+        (Term::Number(n1), Term::Number(n2)) => compare_numbers(n1, n2)
+            .filter(|ordering| ordering.is_ne())
+            .unwrap_or_else(|| matches!(n2, Number::Float(_)).cmp(&matches!(n1, Number::Float(_)))),
+        (Term::Constant(c1), Term::Constant(c2)) | (Term::String(c1), Term::String(c2)) => c1.cmp(c2),
+        (Term::Compound { name: n1, args: a1 }, Term::Compound { name: n2, args: a2 }) => a1
+            .len()
+            .cmp(&a2.len())
+            .then_with(|| n1.cmp(n2))
+            .then_with(|| a1.iter().zip(a2).map(|(x, y)| compare_terms(x, y)).find(|o| o.is_ne()).unwrap_or(Ordering::Equal)),
+        _ => rank(t1).cmp(&rank(t2)),
+    }
+}
+
+pub fn unify_atoms(a1: &Atom, a2: &Atom) -> Option<Substitution> {
     let mut subs = Substitution::new();
-    for (t1, t2) in a1.args.iter().zip(a2.args.iter()) {
-        if !unify_terms(t1, t2, &mut subs) {
-            return None;
-        }
+    if unify_atoms_with(a1, a2, &mut subs) {
+        Some(subs)
+    } else {
+        None
     }
-    Some(subs)
+}
+
+// Unifies two atoms on top of an existing substitution, extending it in place.
+// On failure the substitution may hold partial bindings and should be discarded.
+pub fn unify_atoms_with(a1: &Atom, a2: &Atom, subs: &mut Substitution) -> bool {
+    a1.name == a2.name
+        && a1.args.len() == a2.args.len()
+        && a1.args.iter().zip(a2.args.iter()).all(|(t1, t2)| unify_terms(t1, t2, subs))
 }
 
 // Tests
 #[cfg(test)]
 mod tests {
     use super::*;
+    use num_rational::BigRational;
 
     #[test]
     fn test_unify_constants_equal() {
@@ -126,8 +333,7 @@ mod tests {
 
     #[test]
     fn test_occurs_check() {
-        let mut subs = Substitution::new();
-        let t1 = Term::Variable("X".to_string());
+        let subs = Substitution::new();
         let t2 = Term::Compound {
             name: "f".to_string(),
             args: vec![Term::Variable("X".to_string())],
@@ -138,14 +344,14 @@ mod tests {
 
     #[test]
     fn test_unify_atoms_success() {
-        let a1 = Atom {
-            name: "parent".to_string(),
-            args: vec![Term::Variable("X".to_string()), Term::Variable("Y".to_string())],
-        };
-        let a2 = Atom {
-            name: "parent".to_string(),
-            args: vec![Term::Constant("john".to_string()), Term::Constant("mary".to_string())],
-        };
+        let a1 = Atom::new(
+            "parent",
+            vec![Term::Variable("X".to_string()), Term::Variable("Y".to_string())],
+        );
+        let a2 = Atom::new(
+            "parent",
+            vec![Term::Constant("john".to_string()), Term::Constant("mary".to_string())],
+        );
         let subs = unify_atoms(&a1, &a2).unwrap();
         assert_eq!(subs.get("X").unwrap(), &Term::Constant("john".to_string()));
         assert_eq!(subs.get("Y").unwrap(), &Term::Constant("mary".to_string()));
@@ -153,18 +359,170 @@ mod tests {
 
     #[test]
     fn test_unify_atoms_fail() {
-        let a1 = Atom {
-            name: "parent".to_string(),
-            args: vec![Term::Variable("X".to_string()), Term::Constant("bob".to_string())],
-        };
-        let a2 = Atom {
-            name: "parent".to_string(),
-            args: vec![Term::Constant("john".to_string()), Term::Constant("mary".to_string())],
-        };
+        let a1 = Atom::new(
+            "parent",
+            vec![Term::Variable("X".to_string()), Term::Constant("bob".to_string())],
+        );
+        let a2 = Atom::new(
+            "parent",
+            vec![Term::Constant("john".to_string()), Term::Constant("mary".to_string())],
+        );
         assert!(unify_atoms(&a1, &a2).is_none());
     }
 
-}
+    #[test]
+    fn test_unify_variable_with_itself() {
+        let mut subs = Substitution::new();
+        subs.insert("X".to_string(), Term::Variable("Y".to_string()));
+        assert!(unify_terms(&Term::Variable("X".to_string()), &Term::Variable("Y".to_string()), &mut subs));
+        assert!(unify_terms(&Term::Variable("Y".to_string()), &Term::Variable("Y".to_string()), &mut subs));
+        assert_eq!(subs.len(), 1);
+    }
 
+    #[test]
+    fn test_unify_atoms_with_existing_bindings() {
+        let mut subs = Substitution::new();
+        subs.insert("X".to_string(), Term::Constant("john".to_string()));
+        let goal = Atom::new("parent", vec![Term::Variable("X".to_string())]);
+        let fact = Atom::new("parent", vec![Term::Constant("mary".to_string())]);
+        assert!(!unify_atoms_with(&goal, &fact, &mut subs));
+    }
 
+    #[test]
+    fn test_format_answer() {
+        let mut subs = Substitution::new();
+        assert_eq!(format_answer(&subs), "true");
+        subs.insert("Y".to_string(), Term::Compound {
+            name: "f".to_string(),
+            args: vec![Term::Constant("a".to_string()), Term::Variable("Z".to_string())],
+        });
+        subs.insert("X".to_string(), Term::Constant("john".to_string()));
+        assert_eq!(format_answer(&subs), "X = john, Y = f(a, Z)");
+
+        // unbound and '_' variables are left out, and made-up variables get readable names
+        let var = |name: &str| Term::Variable(name.to_string());
+        let pair = |a: Term, b: Term| Term::Compound { name: "-".to_string(), args: vec![a, b] };
+        let subs = Substitution::from(vec![
+            ("A".to_string(), var("A")),
+            ("B".to_string(), var("X#7")),
+            ("C".to_string(), pair(var("X#7"), pair(var("_#3"), var("_#3")))),
+            ("D".to_string(), pair(var("_#4"), var("_#3"))),
+            ("_E".to_string(), Term::Constant("e".to_string())),
+        ]);
+        assert_eq!(format_answer(&subs), "C = B-(_G1-_G1), D = _G2-_G1");
+        assert_eq!(format_answer(&Substitution::from(vec![("X".to_string(), var("_#1"))])), "true");
+    }
 
+    #[test]
+    fn test_format_lists() {
+        let int = |n: i64| Term::Number(Number::Integer(n));
+        let t = Term::Variable("T".to_string());
+        assert_eq!(format_term(&Term::nil()), "[]");
+        assert_eq!(format_term(&Term::list(vec![int(1), int(2), int(3)], Term::nil())), "[1,2,3]");
+        assert_eq!(format_term(&Term::list(vec![int(1), int(2)], t.clone())), "[1,2|T]");
+        assert_eq!(format_term(&Term::cons(Term::Constant("a".to_string()), Term::Constant("b".to_string()))), "[a|b]");
+        let nested = Term::Compound { name: "f".to_string(), args: vec![Term::list(vec![Term::nil(), t], Term::nil())] };
+        assert_eq!(format_term(&nested), "f([[],T])");
+    }
+
+    #[test]
+    fn test_format_operators() {
+        let format = |src: &str| {
+            let query = crate::parser::parse_query(crate::tokenizer::tokenize(&format!("?- p({}).", src)).unwrap()).unwrap();
+            format_term(&query[0].args[0])
+        };
+        assert_eq!(format("(a :- b, c ; d -> e)"), "a:-b,c;d->e");
+        assert_eq!(format("X is Y + 1 * 2"), "X is Y+1*2");
+        assert_eq!(format("1 - (2 - 3) - 4"), "1-(2-3)-4");
+        assert_eq!(format("(1 + 2) * 3 ^ (2 ^ 3)"), "(1+2)*3^2^3");
+        assert_eq!(format("1 - -1"), "1- -1");
+        assert_eq!(format("- 1"), "-(1)");
+        assert_eq!(format("- (a + b)"), "-(a+b)");
+        assert_eq!(format("- - a"), "- -a");
+        assert_eq!(format("\\+ a = b"), "\\+a=b");
+        assert_eq!(format("f((a, b), [c = d, (e :- f)])"), "f((a,b), [c=d,(e:-f)])");
+        assert_eq!(format("5 mod 2"), "5 mod 2");
+
+        let term = Term::Compound { name: ":-".to_string(), args: vec![Term::Constant("a".to_string()), Term::Constant("b".to_string())] };
+        assert_eq!(format_term(&term), "a:-b");
+        let mut subs = Substitution::new();
+        subs.insert("X".to_string(), term);
+        assert_eq!(format_answer(&subs), "X = (a:-b)");
+
+        // operators declared by a program are written with the table that declares them
+        let mut operators = Operators::default();
+        operators.add(700, crate::operators::OpType::Xfx, "==>");
+        let rule = Term::Compound { name: "==>".to_string(), args: vec![Term::Constant("a".to_string()), Term::Constant("b".to_string())] };
+        assert_eq!(format_term(&rule), "==>(a, b)");
+        assert_eq!(format_term_with(&rule, &operators), "a==>b");
+    }
+
+    #[test]
+    fn test_format_quoted_atoms() {
+        let atom = |name: &str| format_term(&Term::Constant(name.to_string()));
+        assert_eq!(atom("new_york"), "new_york");
+        assert_eq!(atom("New York"), "'New York'");
+        assert_eq!(atom("X"), "'X'");
+        assert_eq!(atom("it's\n"), "'it\\'s\\n'");
+        assert_eq!(atom("=<"), "=<");
+        assert_eq!(atom(","), "','");
+        assert_eq!(atom(""), "''");
+        let compound = Term::Compound { name: "hello world".to_string(), args: vec![Term::String("say \"hi\"".to_string())] };
+        assert_eq!(format_term(&compound), "'hello world'(\"say \\\"hi\\\"\")");
+
+        // strings sort after atoms and before compound terms
+        let (a, s) = (Term::Constant("b".to_string()), Term::String("a".to_string()));
+        assert_eq!(compare_terms(&a, &s), Ordering::Less);
+        assert_eq!(compare_terms(&s, &compound), Ordering::Less);
+        assert!(unify_terms(&s, &Term::String("a".to_string()), &mut Substitution::new()));
+        assert!(!unify_terms(&s, &Term::Constant("a".to_string()), &mut Substitution::new()));
+    }
+
+    #[test]
+    fn test_unify_lists() {
+        let mut subs = Substitution::new();
+        let list = Term::list(vec![Term::Constant("a".to_string()), Term::Constant("b".to_string())], Term::nil());
+        let pattern = Term::cons(Term::Variable("H".to_string()), Term::Variable("T".to_string()));
+        assert!(unify_terms(&pattern, &list, &mut subs));
+        assert_eq!(format_answer(&subs), "H = a, T = [b]");
+        assert!(!unify_terms(&Term::nil(), &list, &mut Substitution::new()));
+    }
+
+    #[test]
+    fn test_standard_order() {
+        let int = |n: i64| Term::Number(Number::Integer(n));
+        let atom = |name: &str| Term::Constant(name.to_string());
+        let f = |name: &str, args: Vec<Term>| Term::Compound { name: name.to_string(), args };
+        let ordered = [
+            Term::Variable("X".to_string()),
+            Term::Number(Number::Float(1.0)),
+            int(1),
+            Term::Number(Number::Rational(BigRational::new(3.into(), 2.into()))),
+            int(2),
+            atom("[]"),
+            atom("a"),
+            f("z", vec![int(1)]),
+            f("a", vec![int(1), int(2)]),
+            f("a", vec![int(2), int(1)]),
+        ];
+        for (i, x) in ordered.iter().enumerate() {
+            for (j, y) in ordered.iter().enumerate() {
+                assert_eq!(compare_terms(x, y), i.cmp(&j), "{} vs {}", format_term(x), format_term(y));
+            }
+        }
+    }
+
+    #[test]
+    fn test_unify_big_numbers() {
+        let big = |digits: &str| Term::Number(Number::BigInt(digits.parse().unwrap()));
+        let third = Term::Number(Number::Rational(BigRational::new(1.into(), 3.into())));
+        let mut subs = Substitution::new();
+        assert!(unify_terms(&big("123456789012345678901234567890"), &big("123456789012345678901234567890"), &mut subs));
+        assert!(!unify_terms(&big("123456789012345678901234567890"), &big("123456789012345678901234567891"), &mut subs));
+        assert!(unify_terms(&Term::Variable("X".to_string()), &third, &mut subs));
+        assert!(!unify_terms(&third, &Term::Number(Number::Float(1.0 / 3.0)), &mut subs));
+        assert_eq!(format_answer(&subs), "X = 1r3");
+        assert_eq!(format_term(&big("-98765432109876543210")), "-98765432109876543210");
+    }
+
+}